@@ -2,7 +2,9 @@
 
 use core::alloc::{GlobalAlloc, Layout};
 
-use super::{align_up, Locked};
+use super::{
+  align_up_checked, poison, Locked, Stats, ALLOC_POISON, FREE_POISON,
+};
 
 /// The [BumpAllocator] type.
 /// It maintains a `next` field that points to the start of unused
@@ -12,6 +14,7 @@ use super::{align_up, Locked};
 #[derive(Debug)]
 pub struct BumpAllocator {
   heap_start: usize,
+  /// End of the heap, exclusive
   heap_end: usize,
   next: usize,
   allocations: usize,
@@ -35,16 +38,51 @@ impl BumpAllocator {
   /// valid virtual addresses.
   pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
     self.heap_start = heap_start;
-    self.heap_end = heap_start + heap_size - 1;
+    self.heap_end = heap_start + heap_size;
     self.next = heap_start;
   }
+
+  /// Extend the heap with the memory region starting at `start`, which
+  /// must directly follow the current heap end. The bump window simply
+  /// grows to include it.
+  ///
+  /// # Safety
+  /// The caller must guarantee that the region is valid and unused.
+  pub unsafe fn extend(&mut self, start: usize, size: usize) {
+    assert_eq!(start, self.heap_end, "region doesn't follow the heap");
+    self.heap_end += size;
+  }
+
+  /// Returns usage statistics of the heap. Memory is only reclaimed
+  /// when all allocations are freed, so everything below `next` counts
+  /// as allocated.
+  pub fn stats(&self) -> Stats {
+    let free_bytes = self.heap_end - self.next;
+    Stats {
+      allocated_bytes: self.next - self.heap_start,
+      free_bytes,
+      allocation_count: self.allocations,
+      largest_free_block: free_bytes,
+    }
+  }
+}
+
+impl Locked<BumpAllocator> {
+  /// Returns usage statistics of the locked allocator.
+  pub fn stats(&self) -> Stats {
+    self.lock().stats()
+  }
 }
 
 unsafe impl GlobalAlloc for Locked<BumpAllocator> {
   unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
     let mut s = self.lock();
 
-    let addr_start = align_up(s.next, layout.align());
+    // running past the end of the address space fails the allocation
+    let addr_start = match align_up_checked(s.next, layout.align()) {
+      Some(start) => start,
+      None => return core::ptr::null_mut(),
+    };
     let addr_end = match addr_start.checked_add(layout.size()) {
       Some(end) => end,
       None => return core::ptr::null_mut(),
@@ -57,15 +95,78 @@ unsafe impl GlobalAlloc for Locked<BumpAllocator> {
     s.next = addr_end;
     s.allocations += 1;
 
+    unsafe { poison(addr_start, layout.size(), ALLOC_POISON) };
     addr_start as *mut u8
   }
 
-  unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
+  /// Freeing more blocks than were allocated, e.g., by a double free, is a
+  /// kernel bug. It fails an assertion in debug builds, and is ignored
+  /// in release builds rather than wrapping the allocation count around,
+  /// which would keep the heap from ever being reset.
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
     let mut s = self.lock();
 
-    s.allocations -= 1;
+    debug_assert!(s.allocations > 0, "dealloc without a live allocation");
+
+    unsafe { poison(ptr as usize, layout.size(), FREE_POISON) };
+
+    s.allocations = s.allocations.saturating_sub(1);
     if s.allocations == 0 {
       s.next = s.heap_start;
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::allocator::test_heap;
+
+  const TEST_HEAP_SIZE: usize = 256;
+
+  fn test_allocator() -> Locked<BumpAllocator> {
+    test_heap::allocator(
+      BumpAllocator::new(),
+      BumpAllocator::init,
+      TEST_HEAP_SIZE,
+    )
+  }
+
+  #[test_case]
+  fn test_alloc_fills_heap() {
+    let allocator = test_allocator();
+    let heap_start = test_heap::start();
+
+    let whole_heap = Layout::from_size_align(TEST_HEAP_SIZE, 1).unwrap();
+    unsafe {
+      let ptr = allocator.alloc(whole_heap);
+      assert_eq!(ptr as usize, heap_start);
+      assert_eq!(allocator.stats().free_bytes, 0);
+      assert!(allocator.alloc(Layout::new::<u8>()).is_null());
+      allocator.dealloc(ptr, whole_heap);
+    }
+    assert_eq!(allocator.stats().free_bytes, TEST_HEAP_SIZE);
+  }
+
+  #[cfg(debug_assertions)]
+  #[test_case]
+  fn test_extra_dealloc_asserts() {
+    use x86_64::instructions::interrupts;
+
+    let allocator = test_allocator();
+
+    let layout = Layout::new::<u64>();
+    let were_enabled = interrupts::are_enabled();
+    crate::test_harness::should_panic(
+      || unsafe {
+        let ptr = allocator.alloc(layout);
+        allocator.dealloc(ptr, layout);
+        allocator.dealloc(ptr, layout);
+      },
+      "dealloc without a live allocation",
+    );
+    // the panic skipped the guard's drop, so catch_panic restored these
+    assert_eq!(interrupts::are_enabled(), were_enabled);
+  }
+}
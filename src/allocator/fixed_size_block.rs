@@ -3,7 +3,7 @@
 
 use core::{alloc::GlobalAlloc, ptr::NonNull};
 
-use super::Locked;
+use super::{poison, Locked, ALLOC_POISON, FREE_POISON};
 
 /// The [ListNode] type describing a free
 /// memory area. No size field is needed as the memory
@@ -17,7 +17,7 @@ struct ListNode {
 ///
 /// The sizes must each be power of 2 because they are also used as
 /// the block alignment (alignments must be always powers of 2).
-const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+pub const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
 
 /// Choose an appropriate block size for the given layout.
 ///
@@ -63,9 +63,74 @@ impl FixedSizeBlockAllocator {
     }
   }
 
+  /// Extend the heap with the memory region starting at `start`, which
+  /// must directly follow the current heap end. The region is given to
+  /// the fall-back allocator, fixed-size blocks are carved out of it on
+  /// demand.
+  ///
+  /// # Safety
+  /// The caller must guarantee that the region is valid and unused.
+  pub unsafe fn extend(&mut self, start: usize, size: usize) {
+    assert_eq!(
+      start,
+      self.fallback_allocator.top(),
+      "region doesn't follow the heap"
+    );
+    unsafe {
+      self.fallback_allocator.extend(size);
+    }
+  }
+
+  /// Count the free blocks in each size class, indexed like
+  /// [BLOCK_SIZES].
+  ///
+  /// Comparing the bytes parked in these lists with
+  /// [fallback_free_bytes](Self::fallback_free_bytes) shows whether a
+  /// large allocation failed because free memory is stuck in small blocks.
+  pub fn free_blocks_per_class(&self) -> [usize; BLOCK_SIZES.len()] {
+    let mut counts = [0; BLOCK_SIZES.len()];
+    for (count, head) in counts.iter_mut().zip(&self.list_heads) {
+      let mut current = head.as_deref();
+      while let Some(node) = current {
+        *count += 1;
+        current = node.next.as_deref();
+      }
+    }
+    counts
+  }
+
+  /// Bytes the fall-back allocator can still hand out, not counting the
+  /// blocks in the fixed-size lists.
+  pub fn fallback_free_bytes(&self) -> usize {
+    self.fallback_allocator.free()
+  }
+
+  /// Give every block in the fixed-size lists back to the fall-back
+  /// allocator, so that their memory can serve allocations of any size
+  /// again.
+  pub fn reclaim(&mut self) {
+    for (head, &block_size) in self.list_heads.iter_mut().zip(BLOCK_SIZES) {
+      // the nodes don't record their size, but every block of a list was
+      // allocated from the fall-back allocator with the same layout
+      let layout =
+        core::alloc::Layout::from_size_align(block_size, block_size).unwrap();
+      while let Some(node) = head.take() {
+        *head = node.next.take();
+        let ptr = NonNull::from(node).cast::<u8>();
+        unsafe { self.fallback_allocator.deallocate(ptr, layout) };
+      }
+    }
+  }
+
   /// Allocate a memory region of given layout using the fall-back
-  /// allocator.
+  /// allocator. If it's out of memory, the cached fixed-size blocks are
+  /// [reclaimed](Self::reclaim) before trying again.
   fn fallback_alloc(&mut self, layout: core::alloc::Layout) -> *mut u8 {
+    if let Ok(region) = self.fallback_allocator.allocate_first_fit(layout) {
+      return region.as_ptr();
+    }
+
+    self.reclaim();
     match self.fallback_allocator.allocate_first_fit(layout) {
       Ok(region) => region.as_ptr(),
       Err(_) => core::ptr::null_mut(),
@@ -73,11 +138,28 @@ impl FixedSizeBlockAllocator {
   }
 }
 
+impl Locked<FixedSizeBlockAllocator> {
+  /// See [FixedSizeBlockAllocator::free_blocks_per_class].
+  pub fn free_blocks_per_class(&self) -> [usize; BLOCK_SIZES.len()] {
+    self.lock().free_blocks_per_class()
+  }
+
+  /// See [FixedSizeBlockAllocator::fallback_free_bytes].
+  pub fn fallback_free_bytes(&self) -> usize {
+    self.lock().fallback_free_bytes()
+  }
+
+  /// See [FixedSizeBlockAllocator::reclaim].
+  pub fn reclaim(&self) {
+    self.lock().reclaim()
+  }
+}
+
 unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
   unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
     let mut allocator = self.lock();
 
-    match list_index(&layout) {
+    let ptr = match list_index(&layout) {
       Some(idx) => {
         match allocator.list_heads[idx].take() {
           Some(head) => {
@@ -100,12 +182,20 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
 
       // Block is too big, use fall back allocation
       None => allocator.fallback_alloc(layout),
+    };
+
+    if !ptr.is_null() {
+      unsafe { poison(ptr as usize, layout.size(), ALLOC_POISON) };
     }
+    ptr
   }
 
   unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
     let mut allocator = self.lock();
 
+    // poison before the list node is written into the block
+    unsafe { poison(ptr as usize, layout.size(), FREE_POISON) };
+
     match list_index(&layout) {
       Some(idx) => {
         let old_head = allocator.list_heads[idx].take();
@@ -130,4 +220,127 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
       }
     }
   }
+
+  /// Blocks from the fixed-size lists still hold the data of earlier
+  /// allocations and the list node, so they must be cleared.
+  unsafe fn alloc_zeroed(&self, layout: core::alloc::Layout) -> *mut u8 {
+    let ptr = unsafe { self.alloc(layout) };
+    if !ptr.is_null() {
+      unsafe { ptr.write_bytes(0, layout.size()) };
+    }
+    ptr
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use core::alloc::Layout;
+
+  use crate::allocator::test_heap;
+
+  const TEST_HEAP_SIZE: usize = test_heap::SIZE;
+
+  fn test_allocator() -> Locked<FixedSizeBlockAllocator> {
+    test_heap::allocator(
+      FixedSizeBlockAllocator::new(),
+      FixedSizeBlockAllocator::init,
+      TEST_HEAP_SIZE,
+    )
+  }
+
+  #[test_case]
+  fn test_free_blocks_per_class() {
+    let allocator = test_allocator();
+    let small = Layout::from_size_align(8, 8).unwrap();
+    let medium = Layout::from_size_align(40, 8).unwrap();
+    let large = Layout::from_size_align(4096, 8).unwrap();
+
+    unsafe {
+      let smalls = [allocator.alloc(small), allocator.alloc(small)];
+      let mediums = [
+        allocator.alloc(medium),
+        allocator.alloc(medium),
+        allocator.alloc(medium),
+      ];
+      let big = allocator.alloc(large);
+      assert_eq!(allocator.free_blocks_per_class(), [0; BLOCK_SIZES.len()]);
+      let free_while_big = allocator.fallback_free_bytes();
+
+      for ptr in smalls {
+        allocator.dealloc(ptr, small);
+      }
+      for ptr in mediums {
+        allocator.dealloc(ptr, medium);
+      }
+      allocator.dealloc(big, large);
+
+      // the small blocks stay in their lists, the large allocation
+      // went back to the fall-back allocator
+      let counts = allocator.free_blocks_per_class();
+      assert_eq!(counts[0], 2);
+      assert_eq!(counts[3], 3);
+      assert_eq!(counts.iter().sum::<usize>(), 5);
+      assert_eq!(allocator.fallback_free_bytes(), free_while_big + 4096);
+    }
+  }
+
+  #[test_case]
+  fn test_reclaim() {
+    let allocator = test_allocator();
+    let small = Layout::from_size_align(64, 64).unwrap();
+    let large = Layout::from_size_align(TEST_HEAP_SIZE / 2, 8).unwrap();
+
+    unsafe {
+      // fill the whole heap with small blocks, then free them all
+      let mut blocks = [core::ptr::null_mut(); TEST_HEAP_SIZE / 64];
+      for block in blocks.iter_mut() {
+        *block = allocator.alloc(small);
+      }
+      for &block in blocks.iter().filter(|block| !block.is_null()) {
+        allocator.dealloc(block, small);
+      }
+
+      // the memory is all stuck in the 64 byte list
+      let mut inner = allocator.lock();
+      assert!(inner.fallback_allocator.allocate_first_fit(large).is_err());
+      inner.reclaim();
+      assert_eq!(inner.free_blocks_per_class(), [0; BLOCK_SIZES.len()]);
+      let region = inner.fallback_allocator.allocate_first_fit(large);
+      let ptr = region.expect("large allocation failed after reclaim");
+      inner.fallback_allocator.deallocate(ptr, large);
+      drop(inner);
+
+      // the allocator reclaims by itself once the fall-back runs dry
+      for block in blocks.iter_mut() {
+        *block = allocator.alloc(small);
+      }
+      for &block in blocks.iter().filter(|block| !block.is_null()) {
+        allocator.dealloc(block, small);
+      }
+      let ptr = allocator.alloc(large);
+      assert!(!ptr.is_null());
+      allocator.dealloc(ptr, large);
+    }
+  }
+
+  #[test_case]
+  fn test_alloc_zeroed_reused_block() {
+    let allocator = test_allocator();
+    let layout = Layout::from_size_align(32, 8).unwrap();
+
+    unsafe {
+      let dirty = allocator.alloc(layout);
+      dirty.write_bytes(0xff, 32);
+      allocator.dealloc(dirty, layout);
+
+      // comes straight from the 32 byte list
+      let ptr = allocator.alloc_zeroed(layout);
+      assert_eq!(ptr, dirty);
+      for i in 0..32 {
+        assert_eq!(*ptr.add(i), 0);
+      }
+      allocator.dealloc(ptr, layout);
+    }
+  }
 }
@@ -2,7 +2,9 @@
 
 use core::alloc::GlobalAlloc;
 
-use super::{align_up, Locked};
+use super::{
+  align_up, align_up_checked, poison, Locked, Stats, ALLOC_POISON, FREE_POISON,
+};
 
 /// A node describes a free memory region for allocation.
 /// It is stored at the head of that memory region itself, and points
@@ -34,12 +36,26 @@ impl ListNode {
   }
 }
 
+/// How [LinkedListAllocator] picks a free region for an allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FitStrategy {
+  /// Use the first region that fits. This is the fastest option.
+  FirstFit,
+  /// Scan the whole free list and use the smallest region that fits,
+  /// keeping larger regions available for larger allocations.
+  BestFit,
+}
+
 /// A [LinkedListAllocator] backed by a free-list construct.
 pub struct LinkedListAllocator {
   // This is always a sentinel node that the allocator owns in its
   // static area. The real nodes describing heap memory are stored
   // at the same heap memory regions.`
   head: ListNode,
+  // bytes handed out to live allocations, after layout adjustment
+  allocated_bytes: usize,
+  allocation_count: usize,
+  strategy: FitStrategy,
 }
 
 impl LinkedListAllocator {
@@ -47,6 +63,34 @@ impl LinkedListAllocator {
   pub const fn new() -> Self {
     Self {
       head: ListNode::new(0),
+      allocated_bytes: 0,
+      allocation_count: 0,
+      strategy: FitStrategy::FirstFit,
+    }
+  }
+
+  /// Set the strategy used to pick free regions, see [FitStrategy].
+  pub fn set_strategy(&mut self, strategy: FitStrategy) {
+    self.strategy = strategy;
+  }
+
+  /// Returns usage statistics of the heap. Free space is computed by
+  /// walking the free list.
+  pub fn stats(&self) -> Stats {
+    let mut free_bytes = 0;
+    let mut largest_free_block = 0;
+    let mut current = &self.head;
+    while let Some(ref region) = current.next {
+      free_bytes += region.size;
+      largest_free_block = largest_free_block.max(region.size);
+      current = region;
+    }
+
+    Stats {
+      allocated_bytes: self.allocated_bytes,
+      free_bytes,
+      allocation_count: self.allocation_count,
+      largest_free_block,
     }
   }
 
@@ -61,24 +105,95 @@ impl LinkedListAllocator {
     }
   }
 
-  /// Adds the given memory region to the front of the list.
+  /// Extend the heap with the given memory region, which is added to
+  /// the free list.
+  ///
+  /// # Safety
+  /// The caller must guarantee that the region is valid and unused.
+  pub unsafe fn extend(&mut self, start: usize, size: usize) {
+    unsafe {
+      self.add_free_region(start, size);
+    }
+  }
+
+  /// Adds the given memory region to the list, which is kept sorted by
+  /// address. The region is merged with the free regions directly
+  /// before and after it, so that the heap doesn't fragment.
   unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
     // addr should be aligned by the size of ListNode
     assert_eq!(addr, align_up(addr, core::mem::align_of::<ListNode>()));
     // size should be enough
     assert!(size >= core::mem::size_of::<ListNode>());
 
+    // find the last region that starts before the new one
+    let mut current = &mut self.head;
+    while matches!(&current.next, Some(next) if next.start_addr() < addr) {
+      current = current.next.as_mut().unwrap();
+    }
+
+    // merge with the following region
+    let mut size = size;
+    let mut next = current.next.take();
+    if matches!(&next, Some(following) if following.start_addr() == addr + size)
+    {
+      let following = next.unwrap();
+      size += following.size;
+      next = following.next.take();
+    }
+
+    // merge with the preceding region, the sentinel head has size 0
+    // and is never merged with
+    if current.size > 0 && current.end_addr() == addr {
+      current.size += size;
+      current.next = next;
+      return;
+    }
+
     // The value of the new list node, stored on kernel stack
     let mut node = ListNode::new(size);
-    node.next = self.head.next.take();
+    node.next = next;
 
     // this is the start address of the freed memory region.
     let node_ptr = addr as *mut ListNode;
     unsafe {
       // write node to that region
       node_ptr.write(node);
-      self.head.next = Some(&mut *node_ptr);
+      current.next = Some(&mut *node_ptr);
+    }
+  }
+
+  /// Try to take the first `size` bytes of the free region starting at
+  /// `addr` out of the free list, so that an allocation ending at `addr`
+  /// can grow in place. The rest of the region stays free.
+  ///
+  /// Returns false if there is no such region or it is too small.
+  fn take_region_at(&mut self, addr: usize, size: usize) -> bool {
+    let mut current = &mut self.head;
+
+    while let Some(ref mut next_region) = current.next {
+      if next_region.start_addr() == addr {
+        if next_region.size < size {
+          return false;
+        }
+        let excess_size = next_region.size - size;
+        if excess_size > 0 && excess_size < core::mem::size_of::<ListNode>() {
+          // the rest couldn't hold a ListNode
+          return false;
+        }
+
+        let next_after = next_region.next.take();
+        current.next = next_after;
+        if excess_size > 0 {
+          unsafe {
+            self.add_free_region(addr + size, excess_size);
+          }
+        }
+        return true;
+      }
+      current = current.next.as_mut().unwrap();
     }
+
+    false
   }
 
   /// Finds an unused region along the free list that is able to hold
@@ -88,10 +203,18 @@ impl LinkedListAllocator {
     size: usize,
     align: usize,
   ) -> Option<(&'static mut ListNode, usize)> {
+    // with best-fit, only the region starting at this address is used
+    let target = match self.strategy {
+      FitStrategy::FirstFit => None,
+      FitStrategy::BestFit => Some(self.best_fit_addr(size, align)?),
+    };
     let mut current = &mut self.head;
 
     while let Some(ref mut next_region) = current.next {
-      if let Ok(alloc_start) = Self::alloc_from_region(next_region, size, align)
+      let is_target =
+        target.is_none() || target == Some(next_region.start_addr());
+      if let (true, Ok(alloc_start)) =
+        (is_target, Self::alloc_from_region(next_region, size, align))
       {
         // Remove next_region from list and return
         let next_after = next_region.next.take();
@@ -106,6 +229,24 @@ impl LinkedListAllocator {
     None
   }
 
+  /// Finds the start address of the smallest region along the free list
+  /// that is able to hold the given size and alignment of allocation.
+  fn best_fit_addr(&self, size: usize, align: usize) -> Option<usize> {
+    let mut best: Option<&ListNode> = None;
+    let mut current = &self.head;
+
+    while let Some(ref region) = current.next {
+      if Self::alloc_from_region(region, size, align).is_ok()
+        && !matches!(best, Some(b) if b.size <= region.size)
+      {
+        best = Some(region);
+      }
+      current = region;
+    }
+
+    best.map(|region| region.start_addr())
+  }
+
   /// Try to use the given region for an allocation with given size and
   /// alignment.
   ///
@@ -116,7 +257,7 @@ impl LinkedListAllocator {
     align: usize,
   ) -> Result<usize, ()> {
     // start address, if any, in this region
-    let alloc_start = align_up(region.start_addr(), align);
+    let alloc_start = align_up_checked(region.start_addr(), align).ok_or(())?;
     let alloc_end = alloc_start.checked_add(size).ok_or(())?;
 
     if alloc_end > region.end_addr() {
@@ -156,6 +297,13 @@ impl LinkedListAllocator {
   }
 }
 
+impl Locked<LinkedListAllocator> {
+  /// Returns usage statistics of the locked allocator.
+  pub fn stats(&self) -> Stats {
+    self.lock().stats()
+  }
+}
+
 unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
   unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
     // perform layout adjustments
@@ -171,6 +319,9 @@ unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
           list.add_free_region(alloc_end, excess_size);
         }
       }
+      list.allocated_bytes += size;
+      list.allocation_count += 1;
+      unsafe { poison(alloc_start, size, ALLOC_POISON) };
       alloc_start as *mut u8
     } else {
       core::ptr::null_mut()
@@ -183,7 +334,267 @@ unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
     let mut list = self.lock();
 
     unsafe {
+      // poison before the list node is written into the region
+      poison(addr, size, FREE_POISON);
       list.add_free_region(addr, size);
     }
+    list.allocated_bytes -= size;
+    list.allocation_count -= 1;
+  }
+
+  /// Freed regions are handed out again as they are, so they still hold the
+  /// data of earlier allocations (or the free poison) and must be cleared.
+  unsafe fn alloc_zeroed(&self, layout: core::alloc::Layout) -> *mut u8 {
+    let ptr = unsafe { self.alloc(layout) };
+    if !ptr.is_null() {
+      unsafe { ptr.write_bytes(0, layout.size()) };
+    }
+    ptr
+  }
+
+  /// Grow or shrink the allocation in place if possible, i.e., if the
+  /// memory right after it is free, or the shrunk tail can be freed.
+  /// Falls back to allocate + copy + deallocate otherwise.
+  unsafe fn realloc(
+    &self,
+    ptr: *mut u8,
+    layout: core::alloc::Layout,
+    new_size: usize,
+  ) -> *mut u8 {
+    let new_layout = unsafe {
+      core::alloc::Layout::from_size_align_unchecked(new_size, layout.align())
+    };
+    let (old_size, _) = LinkedListAllocator::size_align(layout);
+    let (new_size, _) = LinkedListAllocator::size_align(new_layout);
+    let addr = ptr as usize;
+
+    {
+      let mut list = self.lock();
+      if new_size == old_size {
+        return ptr;
+      }
+      if new_size > old_size
+        && list.take_region_at(addr + old_size, new_size - old_size)
+      {
+        list.allocated_bytes += new_size - old_size;
+        unsafe { poison(addr + old_size, new_size - old_size, ALLOC_POISON) };
+        return ptr;
+      }
+      // the freed tail must be able to hold a ListNode
+      if new_size < old_size
+        && old_size - new_size >= core::mem::size_of::<ListNode>()
+      {
+        unsafe {
+          poison(addr + new_size, old_size - new_size, FREE_POISON);
+          list.add_free_region(addr + new_size, old_size - new_size);
+        }
+        list.allocated_bytes -= old_size - new_size;
+        return ptr;
+      }
+    }
+
+    unsafe {
+      let new_ptr = self.alloc(new_layout);
+      if !new_ptr.is_null() {
+        let copy_size = layout.size().min(new_layout.size());
+        core::ptr::copy_nonoverlapping(ptr, new_ptr, copy_size);
+        self.dealloc(ptr, layout);
+      }
+      new_ptr
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use core::alloc::Layout;
+
+  use crate::allocator::test_heap;
+
+  const TEST_HEAP_SIZE: usize = 4096;
+
+  fn test_allocator() -> Locked<LinkedListAllocator> {
+    test_heap::allocator(
+      LinkedListAllocator::new(),
+      LinkedListAllocator::init,
+      TEST_HEAP_SIZE,
+    )
+  }
+
+  #[test_case]
+  fn test_realloc_in_place() {
+    let allocator = test_allocator();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+
+    unsafe {
+      let ptr = allocator.alloc(layout);
+      ptr.write_bytes(0x42, 64);
+
+      let grown = allocator.realloc(ptr, layout, 256);
+      assert_eq!(grown, ptr);
+      for i in 0..64 {
+        assert_eq!(*grown.add(i), 0x42);
+      }
+      allocator.dealloc(grown, Layout::from_size_align(256, 8).unwrap());
+    }
+  }
+
+  #[test_case]
+  fn test_realloc_moves() {
+    let allocator = test_allocator();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+
+    unsafe {
+      let ptr = allocator.alloc(layout);
+      // block the memory right after the allocation
+      let blocker = allocator.alloc(layout);
+      for i in 0..64 {
+        *ptr.add(i) = i as u8;
+      }
+
+      let grown = allocator.realloc(ptr, layout, 256);
+      assert!(!grown.is_null());
+      assert_ne!(grown, ptr);
+      for i in 0..64 {
+        assert_eq!(*grown.add(i), i as u8);
+      }
+      allocator.dealloc(grown, Layout::from_size_align(256, 8).unwrap());
+      allocator.dealloc(blocker, layout);
+    }
+  }
+
+  #[test_case]
+  fn test_dealloc_coalesces() {
+    let allocator = test_allocator();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+
+    unsafe {
+      let blocks = [
+        allocator.alloc(layout),
+        allocator.alloc(layout),
+        allocator.alloc(layout),
+      ];
+      // free the middle block last, so that it has to be merged
+      // with free neighbors on both sides
+      allocator.dealloc(blocks[0], layout);
+      allocator.dealloc(blocks[2], layout);
+      allocator.dealloc(blocks[1], layout);
+    }
+
+    let list = allocator.lock();
+    let region = list.head.next.as_ref().expect("free list is empty");
+    assert_eq!(region.size, TEST_HEAP_SIZE);
+    assert!(region.next.is_none());
+  }
+
+  #[test_case]
+  fn test_stats() {
+    let allocator = test_allocator();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+
+    let ptr = unsafe { allocator.alloc(layout) };
+    let stats = allocator.stats();
+    assert_eq!(stats.allocated_bytes, 64);
+    assert_eq!(stats.allocation_count, 1);
+    assert_eq!(stats.free_bytes, TEST_HEAP_SIZE - 64);
+    assert_eq!(stats.largest_free_block, TEST_HEAP_SIZE - 64);
+
+    unsafe { allocator.dealloc(ptr, layout) };
+    let stats = allocator.stats();
+    assert_eq!(stats.allocated_bytes, 0);
+    assert_eq!(stats.allocation_count, 0);
+    assert_eq!(stats.free_bytes, TEST_HEAP_SIZE);
+  }
+
+  #[test_case]
+  fn test_extend() {
+    let allocator = Locked::new(LinkedListAllocator::new());
+    let half = TEST_HEAP_SIZE / 2;
+    unsafe {
+      let heap_start = test_heap::start();
+      allocator.lock().init(heap_start, half);
+      allocator.lock().extend(heap_start + half, half);
+    }
+
+    // the new region is merged with the existing one
+    let stats = allocator.stats();
+    assert_eq!(stats.free_bytes, TEST_HEAP_SIZE);
+    assert_eq!(stats.largest_free_block, TEST_HEAP_SIZE);
+  }
+
+  #[cfg(feature = "debug_fill")]
+  #[test_case]
+  fn test_debug_fill() {
+    use crate::allocator::{ALLOC_POISON, FREE_POISON};
+
+    let allocator = test_allocator();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+
+    unsafe {
+      let ptr = allocator.alloc(layout);
+      for i in 0..64 {
+        assert_eq!(*ptr.add(i), ALLOC_POISON);
+      }
+      ptr.write_bytes(0, 64);
+      allocator.dealloc(ptr, layout);
+      // the first bytes hold the free list node
+      for i in core::mem::size_of::<ListNode>()..64 {
+        assert_eq!(*ptr.add(i), FREE_POISON);
+      }
+
+      let reused = allocator.alloc(layout);
+      assert_eq!(reused, ptr);
+      for i in 0..64 {
+        assert_eq!(*reused.add(i), ALLOC_POISON);
+      }
+      allocator.dealloc(reused, layout);
+    }
+  }
+
+  #[test_case]
+  fn test_best_fit() {
+    let allocator = test_allocator();
+    allocator.lock().set_strategy(FitStrategy::BestFit);
+    let big = Layout::from_size_align(512, 8).unwrap();
+    let small = Layout::from_size_align(128, 8).unwrap();
+    let separator = Layout::from_size_align(64, 8).unwrap();
+
+    unsafe {
+      // leave a big and a small hole in front of the rest of the heap
+      let big_hole = allocator.alloc(big);
+      let sep_1 = allocator.alloc(separator);
+      let small_hole = allocator.alloc(small);
+      let sep_2 = allocator.alloc(separator);
+      allocator.dealloc(big_hole, big);
+      allocator.dealloc(small_hole, small);
+
+      let layout = Layout::from_size_align(100, 8).unwrap();
+      let ptr = allocator.alloc(layout);
+      assert_eq!(ptr, small_hole);
+
+      allocator.dealloc(ptr, layout);
+      allocator.dealloc(sep_1, separator);
+      allocator.dealloc(sep_2, separator);
+    }
+  }
+
+  #[test_case]
+  fn test_alloc_zeroed_reused_region() {
+    let allocator = test_allocator();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+
+    unsafe {
+      let dirty = allocator.alloc(layout);
+      dirty.write_bytes(0xff, 64);
+      allocator.dealloc(dirty, layout);
+
+      let ptr = allocator.alloc_zeroed(layout);
+      assert_eq!(ptr, dirty);
+      for i in 0..64 {
+        assert_eq!(*ptr.add(i), 0);
+      }
+      allocator.dealloc(ptr, layout);
+    }
   }
 }
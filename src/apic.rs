@@ -0,0 +1,181 @@
+//! This module drives the timer interrupt from the timer of the local
+//! APIC instead of the legacy programmable interval timer.
+//!
+//! The APIC timer counts down at the bus frequency, which isn't known up
+//! front, so [init] first measures it against the PIT and then programs
+//! the APIC timer to fire at the same frequency. The tick counter and
+//! everything derived from it keep working unchanged.
+//!
+//! Only the timer moves to the APIC. There is no I/O APIC support, so the
+//! keyboard still interrupts through the legacy PIC.
+
+use crate::interrupts::{InterruptIndex, PICS};
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::{
+  registers::model_specific::Msr,
+  structures::paging::{
+    mapper::MapToError, FrameAllocator, Mapper, Page, PhysFrame, Size4KiB,
+  },
+  PhysAddr, VirtAddr,
+};
+
+/// Virtual address that [init] maps the local APIC registers to
+pub const LAPIC_START: u64 = 0x_4444_5555_0000;
+
+/// Model specific register holding the physical address of the local APIC
+const IA32_APIC_BASE: u32 = 0x1b;
+/// Bit of [IA32_APIC_BASE] that enables the local APIC
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+/// Bits of [IA32_APIC_BASE] holding the physical address
+const APIC_BASE_ADDRESS: u64 = 0x000f_ffff_ffff_f000;
+
+/// Offsets of the local APIC registers from [LAPIC_START]
+const REG_EOI: usize = 0xb0;
+const REG_SPURIOUS: usize = 0xf0;
+const REG_LVT_TIMER: usize = 0x320;
+const REG_TIMER_INITIAL_COUNT: usize = 0x380;
+const REG_TIMER_CURRENT_COUNT: usize = 0x390;
+const REG_TIMER_DIVIDE: usize = 0x3e0;
+
+/// Bit of the spurious interrupt register that enables the local APIC
+const SPURIOUS_APIC_ENABLE: u32 = 1 << 8;
+/// Vector of spurious interrupts, which are caught by the default handler
+const SPURIOUS_VECTOR: u32 = 0xff;
+/// Bits of the timer entry of the local vector table
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+/// Divide configuration that runs the timer at 1/16 of the bus frequency
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+/// Bit of the primary PIC's mask register for the PIT's line
+const PIC_MASK_PIT: u8 = 1 << 0;
+
+/// Number of PIT ticks [init] measures the APIC timer over
+const CALIBRATION_TICKS: u32 = 10;
+
+/// Whether the APIC timer drives the timer interrupt, set by [init]
+static TIMER_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Reasons why [init] can't switch to the APIC timer.
+#[derive(Debug)]
+pub enum ApicError {
+  /// The CPU has no local APIC
+  Unsupported,
+  /// Mapping the local APIC registers failed
+  Map(MapToError<Size4KiB>),
+}
+
+impl From<MapToError<Size4KiB>> for ApicError {
+  fn from(err: MapToError<Size4KiB>) -> Self {
+    ApicError::Map(err)
+  }
+}
+
+/// Returns whether the timer interrupt comes from the local APIC timer
+/// rather than the PIT.
+pub fn timer_enabled() -> bool {
+  TIMER_ENABLED.load(Ordering::SeqCst)
+}
+
+/// Switch the timer interrupt over from the PIT to the local APIC timer.
+///
+/// The local APIC registers are mapped at [LAPIC_START] with
+/// [map_mmio](crate::memory::map_mmio), and the APIC timer is set up to
+/// fire periodically at the current
+/// [timer_frequency](crate::interrupts::timer_frequency) on the timer
+/// vector. The PIT's line is masked at the PIC afterwards, and
+/// [init_pit](crate::interrupts::init_pit) panics from then on, so the
+/// timer frequency stays fixed.
+///
+/// This measures the APIC timer against timer interrupts, so it must run
+/// after [crate::init] and not inside an interrupt handler, see
+/// [crate::interrupts::sleep_ticks]. It takes about 100 ms. If it fails,
+/// the PIT keeps driving the timer. Once the APIC timer is running,
+/// calling it again does nothing.
+pub fn init(
+  mapper: &mut impl Mapper<Size4KiB>,
+  frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), ApicError> {
+  use x86_64::instructions::interrupts;
+
+  if timer_enabled() {
+    return Ok(());
+  }
+  if !crate::cpu::features().apic {
+    return Err(ApicError::Unsupported);
+  }
+
+  let mut apic_base = Msr::new(IA32_APIC_BASE);
+  let base = unsafe { apic_base.read() };
+  let frame =
+    PhysFrame::containing_address(PhysAddr::new(base & APIC_BASE_ADDRESS));
+  let page = Page::containing_address(VirtAddr::new(LAPIC_START));
+  unsafe {
+    crate::memory::map_mmio(page, frame, mapper, frame_allocator)?;
+    apic_base.write(base | APIC_BASE_ENABLE);
+    write(REG_SPURIOUS, SPURIOUS_APIC_ENABLE | SPURIOUS_VECTOR);
+  }
+
+  let count_per_tick = calibrate();
+  interrupts::without_interrupts(|| {
+    TIMER_ENABLED.store(true, Ordering::SeqCst);
+    unsafe {
+      let mut pics = PICS.lock();
+      let [primary, secondary] = pics.read_masks();
+      pics.write_masks(primary | PIC_MASK_PIT, secondary);
+
+      write(REG_TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+      write(
+        REG_LVT_TIMER,
+        LVT_TIMER_PERIODIC | u32::from(InterruptIndex::Timer.as_u8()),
+      );
+      write(REG_TIMER_INITIAL_COUNT, count_per_tick);
+    }
+  });
+  crate::info!("APIC timer running, {} counts per tick", count_per_tick);
+
+  Ok(())
+}
+
+/// Measure how far the APIC timer counts down during one timer tick,
+/// with the timer's interrupt masked.
+fn calibrate() -> u32 {
+  use crate::interrupts::sleep_ticks;
+
+  unsafe {
+    write(REG_TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+    write(REG_LVT_TIMER, LVT_MASKED);
+  }
+  // start right after a tick, so we measure whole ticks
+  sleep_ticks(1);
+  unsafe { write(REG_TIMER_INITIAL_COUNT, u32::MAX) };
+  sleep_ticks(u64::from(CALIBRATION_TICKS));
+  let elapsed = u32::MAX - unsafe { read(REG_TIMER_CURRENT_COUNT) };
+  // a zero initial count stops the timer
+  unsafe { write(REG_TIMER_INITIAL_COUNT, 0) };
+  (elapsed / CALIBRATION_TICKS).max(1)
+}
+
+/// Signal the end of the current interrupt to the local APIC, so that it
+/// delivers the next one. Must only be called once [timer_enabled].
+pub(crate) fn end_of_interrupt() {
+  debug_assert!(timer_enabled(), "local APIC is not set up");
+  unsafe { write(REG_EOI, 0) };
+}
+
+/// Read the local APIC register at `offset`.
+///
+/// # Safety
+/// The local APIC registers must be mapped at [LAPIC_START].
+unsafe fn read(offset: usize) -> u32 {
+  let register = (LAPIC_START as usize + offset) as *const u32;
+  unsafe { core::ptr::read_volatile(register) }
+}
+
+/// Write `value` to the local APIC register at `offset`.
+///
+/// # Safety
+/// The local APIC registers must be mapped at [LAPIC_START].
+unsafe fn write(offset: usize, value: u32) {
+  let register = (LAPIC_START as usize + offset) as *mut u32;
+  unsafe { core::ptr::write_volatile(register, value) };
+}
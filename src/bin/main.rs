@@ -30,6 +30,13 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
   println!("Hello World!");
   blog_os::init();
 
+  let vendor = blog_os::cpu::vendor();
+  println!(
+    "CPU: {} ({})",
+    core::str::from_utf8(&vendor).unwrap_or("unknown vendor"),
+    blog_os::cpu::features()
+  );
+
   #[cfg(test)]
   {
     test_main();
@@ -38,6 +45,9 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
 
   #[cfg(not(test))]
   {
+    let memory = blog_os::memory::memory_report(&boot_info.memory_map);
+    println!("Physical memory: {}", memory);
+
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
 
     // use OffsetPageTable
@@ -46,7 +56,14 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     let mut frame_allocator =
       unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
 
-    blog_os::allocator::init_heap(&mut mapper, &mut frame_allocator).unwrap();
+    blog_os::allocator::init_heap_default(&mut mapper, &mut frame_allocator)
+      .unwrap();
+    // keep output that scrolls off the screen, see PageUp and PageDown
+    blog_os::vga_buffer::enable_scrollback();
+    #[cfg(feature = "apic")]
+    if let Err(err) = blog_os::apic::init(&mut mapper, &mut frame_allocator) {
+      println!("APIC timer unavailable, using the PIT: {:?}", err);
+    }
 
     // now we can use dynamic allocation
     let mut v = Vec::new();
@@ -64,9 +81,9 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     println!("It did not crash!");
 
     let mut executor = blog_os::task::executor::Executor::new();
-    use blog_os::task::{keyboard::print_keypress, Task};
+    use blog_os::task::{shell, Task};
     executor.spawn(Task::new(example_task()));
-    executor.spawn(Task::new(print_keypress()));
+    shell::spawn(&mut executor);
     executor.run();
 
     // blog_os::hlt_loop();
@@ -88,7 +105,7 @@ async fn example_task() {
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-  println!("{}", info);
+  blog_os::vga_buffer::panic_screen(info);
   blog_os::hlt_loop();
 }
 
@@ -0,0 +1,208 @@
+//! This module detects what the CPU supports through the `cpuid`
+//! instruction, and reads the time stamp counter.
+
+use conquer_once::spin::OnceCell;
+use core::arch::x86_64::{__cpuid, __rdtscp, _rdtsc, CpuidResult};
+
+/// CPUID leaf returning the vendor string
+const LEAF_VENDOR: u32 = 0;
+/// CPUID leaf returning the feature flags
+const LEAF_FEATURES: u32 = 1;
+/// CPUID leaf returning the highest supported extended leaf
+const LEAF_EXTENDED_MAX: u32 = 0x8000_0000;
+/// CPUID leaf returning the extended feature flags
+const LEAF_EXTENDED_FEATURES: u32 = 0x8000_0001;
+
+/// Bits of the feature flags in `edx` of [LEAF_FEATURES]
+const EDX_TSC: u32 = 1 << 4;
+const EDX_APIC: u32 = 1 << 9;
+const EDX_FXSR: u32 = 1 << 24;
+const EDX_SSE: u32 = 1 << 25;
+/// Bits of the feature flags in `ecx` of [LEAF_FEATURES]
+const ECX_X2APIC: u32 = 1 << 21;
+/// Bits of the feature flags in `edx` of [LEAF_EXTENDED_FEATURES]
+const EXT_EDX_RDTSCP: u32 = 1 << 27;
+
+/// Features of the CPU we're running on, see [features].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuFeatures {
+  /// Has a local APIC
+  pub apic: bool,
+  /// The local APIC supports x2APIC mode
+  pub x2apic: bool,
+  /// Supports SSE instructions
+  pub sse: bool,
+  /// Supports saving the FPU and SSE state with `fxsave`/`fxrstor`
+  pub fxsr: bool,
+  /// Has a time stamp counter readable with `rdtsc`
+  pub tsc: bool,
+  /// Supports reading the time stamp counter with `rdtscp`
+  pub rdtscp: bool,
+}
+
+impl core::fmt::Display for CpuFeatures {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    let flags = [
+      ("apic", self.apic),
+      ("x2apic", self.x2apic),
+      ("sse", self.sse),
+      ("fxsr", self.fxsr),
+      ("tsc", self.tsc),
+      ("rdtscp", self.rdtscp),
+    ];
+    let mut first = true;
+    for (name, _) in flags.iter().filter(|(_, present)| *present) {
+      if !first {
+        f.write_str(" ")?;
+      }
+      f.write_str(name)?;
+      first = false;
+    }
+    Ok(())
+  }
+}
+
+/// Run `cpuid` for the given leaf.
+fn cpuid(leaf: u32) -> CpuidResult {
+  // `__cpuid` is only unsafe on older toolchains
+  #[allow(unused_unsafe)]
+  unsafe {
+    __cpuid(leaf)
+  }
+}
+
+/// Detect the features of the CPU.
+pub fn features() -> CpuFeatures {
+  let CpuidResult { ecx, edx, .. } = cpuid(LEAF_FEATURES);
+  let extended_edx = if cpuid(LEAF_EXTENDED_MAX).eax >= LEAF_EXTENDED_FEATURES {
+    cpuid(LEAF_EXTENDED_FEATURES).edx
+  } else {
+    0
+  };
+  CpuFeatures {
+    apic: edx & EDX_APIC != 0,
+    x2apic: ecx & ECX_X2APIC != 0,
+    sse: edx & EDX_SSE != 0,
+    fxsr: edx & EDX_FXSR != 0,
+    tsc: edx & EDX_TSC != 0,
+    rdtscp: extended_edx & EXT_EDX_RDTSCP != 0,
+  }
+}
+
+/// The CPU vendor string, e.g. `GenuineIntel`, or `AuthenticAMD`.
+pub fn vendor() -> [u8; 12] {
+  let CpuidResult { ebx, ecx, edx, .. } = cpuid(LEAF_VENDOR);
+  // the string is spread over ebx, edx and ecx, in that order
+  let mut vendor = [0; 12];
+  vendor[0..4].copy_from_slice(&ebx.to_le_bytes());
+  vendor[4..8].copy_from_slice(&edx.to_le_bytes());
+  vendor[8..12].copy_from_slice(&ecx.to_le_bytes());
+  vendor
+}
+
+/// Read the time stamp counter, which counts CPU cycles since reset.
+///
+/// `rdtsc` isn't serializing, so the CPU may execute it before earlier
+/// instructions have finished. See [rdtscp] for a variant that waits for
+/// them.
+pub fn rdtsc() -> u64 {
+  // `_rdtsc` is only unsafe on older toolchains
+  #[allow(unused_unsafe)]
+  unsafe {
+    _rdtsc()
+  }
+}
+
+/// Read the time stamp counter once all earlier instructions have
+/// executed, or `None` if the CPU doesn't support `rdtscp`.
+pub fn rdtscp() -> Option<u64> {
+  if !features().rdtscp {
+    return None;
+  }
+  let mut aux = 0;
+  // Safety: the CPU supports `rdtscp`, as checked above
+  Some(unsafe { __rdtscp(&mut aux) })
+}
+
+/// Number of timer ticks [calibrate_tsc] measures the time stamp
+/// counter over
+const CALIBRATION_TICKS: u64 = 10;
+
+/// Time stamp counter frequency in Hz, measured by [calibrate_tsc]
+static TSC_FREQUENCY: OnceCell<u64> = OnceCell::uninit();
+
+/// Estimate the frequency of the time stamp counter by counting cycles
+/// across [CALIBRATION_TICKS] timer ticks. The measurement only happens
+/// on the first call, later ones return the same estimate. Returns
+/// `None` if the CPU has no time stamp counter.
+///
+/// This waits for timer interrupts, so it must run after [crate::init]
+/// and not inside an interrupt handler, see
+/// [crate::interrupts::sleep_ticks]. It takes about 100 ms.
+///
+/// Under virtualization the estimate is only approximate: the host may
+/// scale, offset or pause the guest's counter, and the emulated timer
+/// interrupts themselves can arrive late.
+pub fn calibrate_tsc() -> Option<u64> {
+  use crate::interrupts::{sleep_ticks, timer_frequency};
+
+  if !features().tsc {
+    return None;
+  }
+  let frequency = TSC_FREQUENCY.get_or_init(|| {
+    // start right after a tick, so we measure whole ticks
+    sleep_ticks(1);
+    let start = rdtsc();
+    sleep_ticks(CALIBRATION_TICKS);
+    let cycles = rdtsc() - start;
+    cycles * u64::from(timer_frequency()) / CALIBRATION_TICKS
+  });
+  Some(*frequency)
+}
+
+/// Returns the time stamp counter frequency in Hz, or `None` if
+/// [calibrate_tsc] hasn't run yet.
+pub fn tsc_frequency() -> Option<u64> {
+  TSC_FREQUENCY.get().copied()
+}
+
+/// Convert a number of time stamp counter cycles to approximate
+/// nanoseconds, or `None` if [calibrate_tsc] hasn't run yet.
+pub fn cycles_to_ns(cycles: u64) -> Option<u64> {
+  let frequency = tsc_frequency()?;
+  Some((u128::from(cycles) * 1_000_000_000 / u128::from(frequency)) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test_case]
+  fn test_qemu_features() {
+    let features = features();
+    assert!(features.tsc);
+    assert!(features.apic);
+  }
+
+  #[test_case]
+  fn test_rdtsc_advances() {
+    let start = rdtsc();
+    assert!(rdtsc() > start);
+    if let Some(start) = rdtscp() {
+      assert!(rdtscp().unwrap() > start);
+    }
+  }
+
+  #[test_case]
+  fn test_calibrate_tsc() {
+    let frequency = calibrate_tsc().unwrap();
+    assert!(frequency > 0);
+    assert_eq!(tsc_frequency(), Some(frequency));
+    assert_eq!(cycles_to_ns(frequency), Some(1_000_000_000));
+  }
+
+  #[test_case]
+  fn test_vendor_is_ascii() {
+    assert!(vendor().iter().all(u8::is_ascii_graphic));
+  }
+}
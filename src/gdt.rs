@@ -1,14 +1,66 @@
 //! This module contains the global descriptor table of the kernel
 //! and some other structures.
 
+use crate::memory;
+use conquer_once::spin::OnceCell;
+use core::sync::atomic::{AtomicBool, Ordering};
 use x86_64::structures::gdt::{
   Descriptor, GlobalDescriptorTable, SegmentSelector,
 };
+use x86_64::structures::paging::{
+  mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
+};
 use x86_64::structures::tss::TaskStateSegment;
 use x86_64::VirtAddr;
 
+// Each handler running on its own stack needs a unique index into the
+// interrupt stack table, which holds at most 7 stacks. Keep the indices
+// in order and add new ones at the end.
+
 /// The stack table index for the stack used for double fault
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+/// The stack table index for the stack used for page fault
+pub const PAGE_FAULT_IST_INDEX: u16 = 1;
+/// The stack table index for the stack used for non-maskable interrupt
+pub const NMI_IST_INDEX: u16 = 2;
+
+/// Number of interrupt stacks in use
+const IST_STACK_COUNT: usize = 3;
+
+/// Size of each interrupt stack. It is a multiple of the page size, so
+/// that the page-aligned stacks also end on a page boundary.
+pub const STACK_SIZE: usize = 4096 * 5;
+
+const _: () = assert!(STACK_SIZE % 4096 == 0);
+
+/// Start of the virtual memory area holding the guarded interrupt stacks,
+/// see [init_gdt_with_guard].
+pub const GUARDED_STACKS_START: u64 = 0x_5555_0000_0000;
+
+/// Page-aligned backing memory of a statically allocated stack.
+#[repr(align(4096))]
+struct Stack([u8; STACK_SIZE]);
+
+/// Statically allocate a new interrupt stack and evaluate to its top
+/// address, since stacks grow downwards.
+macro_rules! interrupt_stack {
+  () => {{
+    // we don't have memory allocator yet, so this is statically allocated
+    static mut STACK: Stack = Stack([0; STACK_SIZE]);
+    let stack_start =
+      VirtAddr::from_ptr(unsafe { core::ptr::addr_of!(STACK.0) });
+
+    stack_start + STACK_SIZE
+  }};
+}
+
+/// Tops of the guarded stacks mapped by [init_gdt_with_guard], used
+/// instead of the static stacks if set.
+static GUARDED_STACK_TOPS: OnceCell<[VirtAddr; IST_STACK_COUNT]> =
+  OnceCell::uninit();
+
+/// Whether the GDT and TSS have been loaded.
+static LOADED: AtomicBool = AtomicBool::new(false);
 
 lazy_static! {
   // Global descriptor table that contains information needed for kernel and CPU
@@ -22,14 +74,14 @@ lazy_static! {
   // Task State Segment Descriptor that contains the interrupt stack table
   static ref TSS: TaskStateSegment = {
     let mut tss = TaskStateSegment::new();
-    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-      const STACK_SIZE: usize = 4096 * 5;
-      // we don't have memory allocator yet, so this is statically allocated
-      static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-      let stack_start = VirtAddr::from_ptr(unsafe {&STACK});
-
-      stack_start + STACK_SIZE
+    let stack_tops = match GUARDED_STACK_TOPS.get() {
+      Some(tops) => *tops,
+      None => [interrupt_stack!(), interrupt_stack!(), interrupt_stack!()],
     };
+    for (index, &top) in stack_tops.iter().enumerate() {
+      assert!(top.is_aligned(4096u64), "interrupt stack top is not page-aligned");
+      tss.interrupt_stack_table[index] = top;
+    }
     tss
   };
 }
@@ -40,10 +92,17 @@ struct Selectors {
 }
 
 /// Initialize global descriptor table
+///
+/// Does nothing if it is already initialized, as loading the TSS twice
+/// faults.
 pub fn init_gdt() {
   use x86_64::instructions::segmentation::{Segment, CS};
   use x86_64::instructions::tables::load_tss;
 
+  if LOADED.swap(true, Ordering::SeqCst) {
+    return;
+  }
+
   GDT.0.load();
 
   unsafe {
@@ -53,3 +112,63 @@ pub fn init_gdt() {
     load_tss(GDT.1.tss_selector);
   }
 }
+
+/// Initialize global descriptor table like [init_gdt], but with the
+/// interrupt stacks mapped from [GUARDED_STACKS_START] onwards. Each stack
+/// has an unmapped guard page below it, so that overflowing it faults
+/// instead of corrupting adjacent memory.
+///
+/// This must be called before [init_gdt], i.e., before [crate::init].
+pub fn init_gdt_with_guard(
+  mapper: &mut impl Mapper<Size4KiB>,
+  frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+  assert!(!LOADED.load(Ordering::SeqCst), "GDT is already initialized");
+
+  let stack_pages = (STACK_SIZE / 4096) as u64;
+  let mut stack_tops = [VirtAddr::zero(); IST_STACK_COUNT];
+  let mut page = Page::containing_address(VirtAddr::new(GUARDED_STACKS_START));
+  for top in &mut stack_tops {
+    // leave the guard page unmapped
+    let stack_start = page + 1;
+    let flags = PageTableFlags::PRESENT
+      | PageTableFlags::WRITABLE
+      | PageTableFlags::NO_EXECUTE;
+    memory::map_range(
+      stack_start,
+      stack_pages,
+      flags,
+      mapper,
+      frame_allocator,
+    )?;
+    *top = stack_start.start_address() + STACK_SIZE;
+    page = stack_start + stack_pages;
+  }
+
+  GUARDED_STACK_TOPS.init_once(|| stack_tops);
+  init_gdt();
+  Ok(())
+}
+
+/// Returns the top address of the interrupt stack with the given index
+/// of the interrupt stack table.
+pub fn interrupt_stack_top(ist_index: u16) -> VirtAddr {
+  TSS.interrupt_stack_table[ist_index as usize]
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test_case]
+  fn test_interrupt_stacks_distinct() {
+    let indices = [DOUBLE_FAULT_IST_INDEX, PAGE_FAULT_IST_INDEX, NMI_IST_INDEX];
+    for (i, &a) in indices.iter().enumerate() {
+      for &b in &indices[i + 1..] {
+        let top_a = interrupt_stack_top(a).as_u64();
+        let top_b = interrupt_stack_top(b).as_u64();
+        assert!(top_a.abs_diff(top_b) >= STACK_SIZE as u64);
+      }
+    }
+  }
+}
@@ -7,6 +7,7 @@ use x86_64::structures::idt::{
 };
 
 use crate::{hlt_loop, println};
+use core::sync::atomic::{AtomicPtr, AtomicU32, AtomicU64, Ordering};
 use pic8259::ChainedPics;
 use spin;
 
@@ -30,7 +31,7 @@ pub enum InterruptIndex {
 
 impl InterruptIndex {
   /// Convert enum to u8 value
-  fn as_u8(self) -> u8 {
+  pub(crate) fn as_u8(self) -> u8 {
     self as u8
   }
 
@@ -40,6 +41,19 @@ impl InterruptIndex {
   }
 }
 
+/// Install [unhandled_interrupt_handler] on the vectors `hi * 16 + lo`
+/// for every given `hi` and every `lo` in 0..16.
+macro_rules! set_default_handlers {
+  ($idt:ident; $($hi:literal),*) => {
+    $( set_default_handlers!(@row $idt, $hi;
+      0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15); )*
+  };
+  (@row $idt:ident, $hi:literal; $($lo:literal),*) => {
+    $( $idt[$hi * 16 + $lo]
+      .set_handler_fn(unhandled_interrupt_handler::<{ $hi * 16 + $lo }>); )*
+  };
+}
+
 lazy_static! {
   // Interrupt Descriptor Table contains CPU exception & hardware interrupt handlers
   static ref IDT: InterruptDescriptorTable = {
@@ -48,7 +62,30 @@ lazy_static! {
     unsafe {
       idt.double_fault.set_handler_fn(double_fault_handler).set_stack_index(crate::gdt::DOUBLE_FAULT_IST_INDEX);
     }
+    idt.invalid_tss.set_handler_fn(invalid_tss_handler);
+    idt.segment_not_present.set_handler_fn(segment_not_present_handler);
+    idt.stack_segment_fault.set_handler_fn(stack_segment_fault_handler);
     idt.page_fault.set_handler_fn(page_fault_handler);
+    idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
+    unsafe {
+      idt.non_maskable_interrupt.set_handler_fn(nmi_handler).set_stack_index(crate::gdt::NMI_IST_INDEX);
+    }
+    idt.machine_check.set_handler_fn(machine_check_handler);
+
+    // catch all interrupts that don't have a dedicated handler below
+    set_default_handlers!(idt; 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15);
+
+    // the system call entry needs the caller's registers, which an
+    // `x86-interrupt` handler can't reliably read, so it uses the naked
+    // entry of the other interrupt implementation. Ring 3 may only raise
+    // interrupts whose gate has a privilege level of 3, otherwise `int`
+    // itself raises a general protection fault
+    let syscall_entry = crate::naked_interrupts::syscall_entry() as usize;
+    unsafe {
+      idt[usize::from(SYSCALL_VECTOR)]
+        .set_handler_addr(x86_64::VirtAddr::new(syscall_entry as u64))
+        .set_privilege_level(x86_64::PrivilegeLevel::Ring3);
+    }
 
     // set up timer interrupt handler
     idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
@@ -64,15 +101,150 @@ lazy_static! {
   });
 }
 
+/// Number of timer interrupts received since boot
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the number of timer interrupts received since boot.
+///
+/// The counter advances at the frequency of the programmable interval
+/// timer, which is [TIMER_FREQUENCY_HZ] once [init_pit] has run during
+/// [crate::init], and its power-on default of about 18.2 Hz
+/// (1193182 Hz / 65536) before that. The local APIC timer set up by
+/// [crate::apic::init] keeps the frequency of the PIT.
+pub fn ticks() -> u64 {
+  TICKS.load(Ordering::Relaxed)
+}
+
+/// The `fn(u64)` installed with [set_tick_hook], or null. It's kept as a
+/// pointer so the timer interrupt handler can read it without a lock.
+static TICK_HOOK: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Install a hook that runs on every timer tick with the new tick count,
+/// replacing any hook installed before. It runs inside the timer
+/// interrupt handler, so it must not block.
+///
+/// The test harness uses this to time out hanging tests.
+pub fn set_tick_hook(hook: fn(u64)) {
+  TICK_HOOK.store(hook as *mut (), Ordering::SeqCst);
+}
+
+/// Frequency of the oscillator driving the programmable interval timer
+const PIT_BASE_FREQUENCY: u32 = 1193182;
+/// Timer interrupt frequency set up by [crate::init]
+pub const TIMER_FREQUENCY_HZ: u32 = 100;
+
+/// The reload value currently programmed into the PIT. The timer
+/// fires every `PIT_DIVISOR` oscillations, and defaults to 65536 at power-on.
+static PIT_DIVISOR: AtomicU32 = AtomicU32::new(65536);
+
+/// Program channel 0 of the programmable interval timer, which drives
+/// the timer interrupt, to fire `hz` times per second.
+///
+/// Panics if `hz` can't be reached with the PIT's 16-bit reload value,
+/// i.e., if it is below 19 Hz or above 1193182 Hz. Also panics once the
+/// local APIC timer drives the timer interrupt, see [crate::apic::init],
+/// as the tick conversions would then use a frequency it doesn't run at.
+pub fn init_pit(hz: u32) {
+  use x86_64::instructions::interrupts;
+  use x86_64::instructions::port::Port;
+
+  assert!(
+    !crate::apic::timer_enabled(),
+    "the APIC timer drives the timer interrupt, the PIT can't be reprogrammed"
+  );
+  assert!(hz > 0, "PIT frequency must be positive");
+  let divisor = PIT_BASE_FREQUENCY / hz;
+  assert!(
+    (1..=u32::from(u16::MAX)).contains(&divisor),
+    "PIT frequency {} Hz out of range",
+    hz
+  );
+
+  let mut command: Port<u8> = Port::new(0x43);
+  let mut channel0: Port<u8> = Port::new(0x40);
+  interrupts::without_interrupts(|| {
+    unsafe {
+      // channel 0, access low then high byte, mode 3 (square wave), binary
+      command.write(0x36);
+      channel0.write(divisor as u8);
+      channel0.write((divisor >> 8) as u8);
+    }
+    PIT_DIVISOR.store(divisor, Ordering::Relaxed);
+  });
+}
+
+/// Returns the frequency at which the tick counter advances, in Hz.
+pub fn timer_frequency() -> u32 {
+  PIT_BASE_FREQUENCY / PIT_DIVISOR.load(Ordering::Relaxed)
+}
+
+/// Convert a number of ticks to milliseconds at the current timer frequency,
+/// rounded to the nearest millisecond.
+pub fn ticks_to_ms(ticks: u64) -> u64 {
+  let divisor = u64::from(PIT_DIVISOR.load(Ordering::Relaxed));
+  let base = u64::from(PIT_BASE_FREQUENCY);
+  (ticks * divisor * 1000 + base / 2) / base
+}
+
+/// Convert milliseconds to a number of ticks at the current timer
+/// frequency, rounded to the nearest tick.
+pub fn ms_to_ticks(ms: u64) -> u64 {
+  let divisor = u64::from(PIT_DIVISOR.load(Ordering::Relaxed));
+  let base = u64::from(PIT_BASE_FREQUENCY);
+  (ms * base + divisor * 500) / (divisor * 1000)
+}
+
+/// Returns the time since boot in milliseconds, as measured by the timer.
+pub fn uptime_ms() -> u64 {
+  ticks_to_ms(ticks())
+}
+
+/// Halt until the tick counter has advanced by at least `n` ticks.
+///
+/// Interrupts are enabled while waiting (and stay enabled afterwards) so
+/// that timer interrupts can arrive. This must therefore not be called
+/// from inside an interrupt handler.
+pub fn sleep_ticks(n: u64) {
+  use x86_64::instructions::interrupts::enable_and_hlt;
+
+  let start = ticks();
+  while ticks() - start < n {
+    // enabling interrupts and halting happens atomically, so a tick
+    // arriving in between can't leave us halted forever
+    enable_and_hlt();
+  }
+}
+
 /// Initialize interupt descripter table
 pub fn init_idt() {
   // load IDT using lidt instruction
   IDT.load();
 }
 
+/// Number of breakpoint exceptions handled since boot
+static BREAKPOINT_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Raise a breakpoint exception. The handler prints the interrupted frame
+/// and returns, so execution simply resumes after the breakpoint.
+pub fn breakpoint() {
+  x86_64::instructions::interrupts::int3();
+}
+
+/// Returns the number of breakpoint exceptions handled since boot, e.g. to
+/// check in a test that the code path containing a [breakpoint] ran.
+pub fn breakpoint_count() -> u64 {
+  BREAKPOINT_COUNT.load(Ordering::SeqCst)
+}
+
+/// Breakpoint work shared with the naked breakpoint handler.
+pub(crate) fn handle_breakpoint() {
+  BREAKPOINT_COUNT.fetch_add(1, Ordering::SeqCst);
+}
+
 /// BreakPoint exception is raised when CPU executes the `int3` instructions,
 /// it is commonly used by debuggers for setting up break points in the program.
 extern "x86-interrupt" fn breakpoint_handler(frame: InterruptStackFrame) {
+  handle_breakpoint();
   println!("EXCEPTION: BREAKPOINT\n{:#?}", frame);
 }
 
@@ -90,6 +262,169 @@ extern "x86-interrupt" fn page_fault_handler(
   hlt_loop();
 }
 
+/// Invalid TSS is raised when a task switch or a stack switch
+/// references an invalid task state segment.
+extern "x86-interrupt" fn invalid_tss_handler(
+  frame: InterruptStackFrame,
+  error_code: u64,
+) {
+  println!("EXCEPTION: INVALID TSS");
+  println!("Segment Selector: {}", SelectorErrorCode(error_code));
+  println!("{:#?}", frame);
+  hlt_loop();
+}
+
+/// Segment not present is raised when loading a segment or gate
+/// whose present bit is cleared.
+extern "x86-interrupt" fn segment_not_present_handler(
+  frame: InterruptStackFrame,
+  error_code: u64,
+) {
+  println!("EXCEPTION: SEGMENT NOT PRESENT");
+  println!("Segment Selector: {}", SelectorErrorCode(error_code));
+  println!("{:#?}", frame);
+  hlt_loop();
+}
+
+/// Stack segment fault is raised when loading a non-present stack segment,
+/// or when a stack access exceeds the stack segment limit.
+extern "x86-interrupt" fn stack_segment_fault_handler(
+  frame: InterruptStackFrame,
+  error_code: u64,
+) {
+  println!("EXCEPTION: STACK SEGMENT FAULT");
+  // the error code is zero if the fault wasn't caused by loading a selector
+  if error_code != 0 {
+    println!("Segment Selector: {}", SelectorErrorCode(error_code));
+  }
+  println!("{:#?}", frame);
+  hlt_loop();
+}
+
+/// General protection fault is raised on various protection violations,
+/// e.g., executing a privileged instruction or loading an invalid segment.
+extern "x86-interrupt" fn general_protection_fault_handler(
+  frame: InterruptStackFrame,
+  error_code: u64,
+) {
+  println!("EXCEPTION: GENERAL PROTECTION FAULT");
+  if error_code != 0 {
+    println!("Segment Selector: {}", SelectorErrorCode(error_code));
+  }
+  println!("{:#?}", frame);
+  hlt_loop();
+}
+
+/// Vector of the software interrupt used for system calls. The call number
+/// is passed in `rax`, the argument in `rdi`.
+pub const SYSCALL_VECTOR: u8 = 0x80;
+/// Print the character whose code is the argument, returns 0.
+pub const SYS_WRITE: u64 = 1;
+/// Halt with the argument as exit code, never returns.
+pub const SYS_EXIT: u64 = 2;
+/// Returned for unknown system calls
+pub const SYSCALL_UNKNOWN: u64 = u64::MAX;
+
+/// System call work behind the naked system call entry, which both
+/// interrupt implementations install. Returns the result of the call,
+/// which the entry passes back in `rax`.
+pub(crate) fn handle_syscall(number: u64, arg: u64) -> u64 {
+  match number {
+    SYS_WRITE => {
+      let c = char::from_u32(arg as u32).unwrap_or(char::REPLACEMENT_CHARACTER);
+      crate::print!("{}", c);
+      0
+    }
+    SYS_EXIT => {
+      println!("exited with code {}", arg);
+      hlt_loop();
+    }
+    _ => SYSCALL_UNKNOWN,
+  }
+}
+
+/// Non-maskable interrupts signal hardware failures or watchdog timeouts.
+/// They can arrive in the middle of any other handler, so this one runs on
+/// its own stack.
+///
+/// `without_interrupts` doesn't mask NMIs either, so the interrupted code
+/// may hold the lock of the VGA writer or the serial port. The report only
+/// goes through the non-blocking print functions, and is dropped if both
+/// are locked.
+extern "x86-interrupt" fn nmi_handler(frame: InterruptStackFrame) {
+  fn report(args: core::fmt::Arguments) {
+    if !crate::vga_buffer::try_print(args) {
+      crate::serial::try_serial_print(args);
+    }
+  }
+
+  report(format_args!("NON-MASKABLE INTERRUPT\n{:#?}\n", frame));
+}
+
+/// Model specific registers describing a machine check, see
+/// [machine_check_handler].
+const IA32_MCG_CAP: u32 = 0x179;
+const IA32_MCG_STATUS: u32 = 0x17a;
+const IA32_MC0_STATUS: u32 = 0x401;
+const IA32_MC0_ADDR: u32 = 0x402;
+/// Bits of the error reporting bank status registers
+const MCI_STATUS_VALID: u64 = 1 << 63;
+const MCI_STATUS_ADDR_VALID: u64 = 1 << 58;
+
+/// Machine check is raised when the processor detects an internal error
+/// or a bus error. It can't be recovered from, so the handler dumps the
+/// error reporting banks that hold an error and halts.
+extern "x86-interrupt" fn machine_check_handler(
+  frame: InterruptStackFrame,
+) -> ! {
+  use x86_64::registers::model_specific::Msr;
+
+  println!("EXCEPTION: MACHINE CHECK");
+  // the processor supports machine check architecture, or we wouldn't
+  // be here, so these registers exist
+  unsafe {
+    println!("MCG_STATUS: {:#x}", Msr::new(IA32_MCG_STATUS).read());
+    // the low byte of the capabilities is the number of banks
+    let banks = Msr::new(IA32_MCG_CAP).read() & 0xff;
+    for bank in 0..banks as u32 {
+      let status = Msr::new(IA32_MC0_STATUS + 4 * bank).read();
+      if status & MCI_STATUS_VALID == 0 {
+        continue;
+      }
+      println!("MC{}_STATUS: {:#x}", bank, status);
+      if status & MCI_STATUS_ADDR_VALID != 0 {
+        let addr = Msr::new(IA32_MC0_ADDR + 4 * bank).read();
+        println!("MC{}_ADDR: {:#x}", bank, addr);
+      }
+    }
+  }
+  println!("{:#?}", frame);
+  hlt_loop();
+}
+
+/// The error code pushed by segment related exceptions, which
+/// describes the segment selector that caused the exception.
+/// Its layout is:
+/// Bits        Name                Description
+/// 0           External            The exception originated externally to the processor
+/// 1-2         Table               0: GDT, 1 or 3: IDT, 2: LDT
+/// 3-15        Index               Index of the selector in the table
+#[derive(Debug, Clone, Copy)]
+struct SelectorErrorCode(u64);
+
+impl core::fmt::Display for SelectorErrorCode {
+  fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+    let table = match (self.0 >> 1) & 0b11 {
+      0 => "GDT",
+      2 => "LDT",
+      _ => "IDT",
+    };
+    let index = (self.0 >> 3) & 0x1fff;
+    let external = self.0 & 1 != 0;
+    write!(f, "{}[{}] (external: {})", table, index, external)
+  }
+}
+
 /// Double fault is triggered when a CPU exception occurs but the cpu failed to invoke
 /// the corresponding handler.
 /// We catch double fault to avoid the fatal triple fault which causes the system to reset.
@@ -102,14 +437,79 @@ extern "x86-interrupt" fn double_fault_handler(
 
 /// Handles timer interrupt.
 extern "x86-interrupt" fn timer_interrupt_handler(_frame: InterruptStackFrame) {
-  // nothing todo
+  handle_timer();
+}
+
+/// Counts a timer tick and acknowledges the interrupt. Shared by the timer
+/// handlers of both interrupt implementations.
+pub(crate) fn handle_timer() {
+  let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+  // wake up the tasks sleeping until now
+  crate::task::timer::wake_sleepers(now);
+  let hook = TICK_HOOK.load(Ordering::Relaxed);
+  if !hook.is_null() {
+    // Safety: set_tick_hook only stores `fn(u64)` pointers
+    let hook = unsafe { core::mem::transmute::<*mut (), fn(u64)>(hook) };
+    hook(now);
+  }
 
   // PIC expects to receive an "end-of-interrupt" signal so that it will send the next
   // interrupt. Sending this signal to notify PIC that we're done processing the current interrupt
+  if crate::apic::timer_enabled() {
+    // the local APIC timer fired instead, see [crate::apic::init]
+    crate::apic::end_of_interrupt();
+  } else {
+    unsafe {
+      PICS
+        .lock()
+        .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+    }
+  }
+}
+
+/// Default handler for the interrupt vectors 32 to 255 that don't have
+/// a dedicated handler. It reports the vector instead of letting the CPU
+/// fault on a missing IDT entry.
+extern "x86-interrupt" fn unhandled_interrupt_handler<const VECTOR: u8>(
+  _frame: InterruptStackFrame,
+) {
+  crate::warn!("unhandled interrupt {}", VECTOR);
+
+  // acknowledge the PIC, otherwise it won't deliver this line again
+  if !(PIC_1_OFFSET..PIC_2_OFFSET + 8).contains(&VECTOR) {
+    return;
+  }
+  let mut pics = PICS.lock();
+  if VECTOR == PIC_1_OFFSET + 7 && !irq7_in_service(PIC_1_COMMAND) {
+    // spurious IRQ 7, an end of interrupt would acknowledge whichever
+    // other interrupt the primary PIC has in service
+    return;
+  }
+  if VECTOR == PIC_2_OFFSET + 7 && !irq7_in_service(PIC_2_COMMAND) {
+    // spurious IRQ 15, only the primary PIC's cascade line was raised
+    unsafe { pics.notify_end_of_interrupt(PIC_1_OFFSET + 2) };
+    return;
+  }
+  unsafe { pics.notify_end_of_interrupt(VECTOR) };
+}
+
+/// Command port of the primary PIC
+const PIC_1_COMMAND: u16 = 0x20;
+/// Command port of the secondary PIC
+const PIC_2_COMMAND: u16 = 0xa0;
+
+/// Returns whether the PIC at `command_port` has its line 7 in service.
+/// If it raised that line without having it in service, the interrupt is
+/// spurious, e.g., because the device deasserted its line too early.
+fn irq7_in_service(command_port: u16) -> bool {
+  use x86_64::instructions::port::Port;
+
+  // makes the next read of the command port return the in-service register
+  const READ_ISR: u8 = 0x0b;
+  let mut port: Port<u8> = Port::new(command_port);
   unsafe {
-    PICS
-      .lock()
-      .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+    port.write(READ_ISR);
+    port.read() & 1 << 7 != 0
   }
 }
 
@@ -117,9 +517,89 @@ extern "x86-interrupt" fn timer_interrupt_handler(_frame: InterruptStackFrame) {
 extern "x86-interrupt" fn keyboard_interrupt_handler(
   _frame: InterruptStackFrame,
 ) {
+  handle_keyboard();
+}
+
+/// Data port of the PS/2 controller
+const KEYBOARD_DATA_PORT: u16 = 0x60;
+/// Status port of the PS/2 controller
+const KEYBOARD_STATUS_PORT: u16 = 0x64;
+
+/// Turn the keyboard's Caps Lock, Num Lock and Scroll Lock LEDs on or off.
+///
+/// The keyboard acknowledges the command with `0xfa`, which arrives
+/// through the keyboard interrupt like a scancode and is ignored by the
+/// decoder.
+pub fn set_keyboard_leds(caps: bool, num: bool, scroll: bool) {
+  const SET_LEDS: u8 = 0xed;
+  let mask = u8::from(scroll) | u8::from(num) << 1 | u8::from(caps) << 2;
+  write_keyboard(SET_LEDS);
+  write_keyboard(mask);
+}
+
+/// Write a byte to the keyboard once the controller's input buffer is empty.
+fn write_keyboard(byte: u8) {
+  use x86_64::instructions::port::Port;
+
+  wait_keyboard_input_empty();
+  unsafe { Port::new(KEYBOARD_DATA_PORT).write(byte) };
+}
+
+/// Wait until the PS/2 controller is ready to accept another byte.
+fn wait_keyboard_input_empty() {
+  use x86_64::instructions::port::PortReadOnly;
+
+  let mut status = PortReadOnly::<u8>::new(KEYBOARD_STATUS_PORT);
+  // give up eventually rather than hang if there is no controller
+  for _ in 0..100_000 {
+    // bit 1 is set while the input buffer is full
+    if unsafe { status.read() } & 0b10 == 0 {
+      break;
+    }
+    core::hint::spin_loop();
+  }
+}
+
+/// Restart the machine.
+///
+/// The reset line is pulsed through the PS/2 controller, which works in
+/// QEMU and on most PCs. Machines without a PS/2 controller ignore that,
+/// so if we're still running afterwards, we load an empty IDT and raise an
+/// exception: it can't be delivered, which escalates to a triple fault,
+/// and the CPU resets itself.
+pub fn reboot() -> ! {
+  use x86_64::instructions::{port::Port, tables::lidt};
+  use x86_64::structures::DescriptorTablePointer;
+  use x86_64::VirtAddr;
+
+  /// Controller command that pulses the CPU reset line
+  const PULSE_RESET: u8 = 0xfe;
+
+  x86_64::instructions::interrupts::disable();
+  wait_keyboard_input_empty();
+  unsafe { Port::new(KEYBOARD_STATUS_PORT).write(PULSE_RESET) };
+
+  // the reset may take a moment to come through
+  for _ in 0..100_000 {
+    core::hint::spin_loop();
+  }
+
+  let empty_idt = DescriptorTablePointer {
+    limit: 0,
+    base: VirtAddr::zero(),
+  };
+  unsafe { lidt(&empty_idt) };
+  x86_64::instructions::interrupts::int3();
+
+  hlt_loop();
+}
+
+/// Queues the pending scancode and acknowledges the PIC. Shared by the
+/// keyboard handlers of both interrupt implementations.
+pub(crate) fn handle_keyboard() {
   use x86_64::instructions::port::Port;
 
-  let mut port = Port::new(0x60);
+  let mut port = Port::new(KEYBOARD_DATA_PORT);
   let scancode: u8 = unsafe { port.read() };
   // add the scancode to the global queue for asynchronous processing
   crate::task::keyboard::add_scancode(scancode);
@@ -133,10 +613,53 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(
 
 #[cfg(test)]
 mod tests {
+  use super::*;
+
+  #[test_case]
+  fn test_timer_frequency() {
+    assert_eq!(timer_frequency(), TIMER_FREQUENCY_HZ);
+    assert_eq!(ticks_to_ms(u64::from(TIMER_FREQUENCY_HZ)), 1000);
+    assert_eq!(ms_to_ticks(1000), u64::from(TIMER_FREQUENCY_HZ));
+  }
+
+  #[test_case]
+  fn test_sleep_ticks() {
+    let start = ticks();
+    sleep_ticks(2);
+    assert!(ticks() >= start + 2);
+  }
+
   #[test_case]
   fn test_breakpoint_exception() {
     // invoke a breakpoint exception
     // should not crash
-    x86_64::instructions::interrupts::int3();
+    let count = breakpoint_count();
+    breakpoint();
+    assert_eq!(breakpoint_count(), count + 1);
+  }
+
+  #[test_case]
+  fn test_nmi_returns() {
+    use core::arch::asm;
+
+    // a software interrupt runs the same handler as a real NMI
+    unsafe { asm!("int 2") };
+  }
+
+  #[test_case]
+  fn test_syscall_returns_result() {
+    use core::arch::asm;
+
+    let mut result: u64 = SYS_WRITE;
+    unsafe {
+      asm!("int 0x80", inout("rax") result, in("rdi") u64::from(b'\n'));
+    }
+    assert_eq!(result, 0);
+
+    let mut result: u64 = 0xdead;
+    let mut arg: u64 = 42;
+    unsafe { asm!("int 0x80", inout("rax") result, inout("rdi") arg) };
+    assert_eq!(result, SYSCALL_UNKNOWN);
+    assert_eq!(arg, 42);
   }
 }
@@ -18,8 +18,11 @@ extern crate lazy_static;
 extern crate alloc;
 
 pub mod allocator;
+pub mod apic;
+pub mod cpu;
 pub mod gdt;
 pub mod interrupts;
+pub mod log;
 pub mod memory;
 pub mod naked_interrupts;
 pub mod serial;
@@ -58,7 +61,21 @@ entry_point!(test_kernel_main);
 #[cfg(test)]
 #[no_mangle]
 pub fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
+  use x86_64::VirtAddr;
+
   init();
+
+  // set up the heap so that unit tests can allocate
+  let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+  let mut mapper = unsafe { memory::init_offset_page_table(phys_mem_offset) };
+  let mut frame_allocator =
+    unsafe { memory::BootInfoFrameAllocator::init(&boot_info.memory_map) };
+  allocator::init_heap_default(&mut mapper, &mut frame_allocator)
+    .expect("heap initialization failed");
+  #[cfg(feature = "apic")]
+  apic::init(&mut mapper, &mut frame_allocator)
+    .expect("APIC timer initialization failed");
+
   test_main();
   exit_qemu(QemuExitCode::Success)
 }
@@ -71,6 +88,8 @@ pub fn init() {
   unsafe {
     interrupts::PICS.lock().initialize();
   }
+  // run the timer at a known frequency
+  interrupts::init_pit(interrupts::TIMER_FREQUENCY_HZ);
   // enable hardware interrupts
   x86_64::instructions::interrupts::enable();
 }
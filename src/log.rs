@@ -0,0 +1,352 @@
+//! This module provides level-based logging on top of the VGA buffer,
+//! optionally mirrored to the serial port.
+//!
+//! Messages are logged with the [error!](crate::error), [warn!](crate::warn),
+//! [info!](crate::info), [debug!](crate::debug) and [trace!](crate::trace)
+//! macros, and are dropped if they are less severe than [log_level].
+//!
+//! Every logged line is also kept in a fixed-size in-memory buffer, which
+//! can be read back with [dump], e.g., to check from a test what the
+//! kernel logged.
+
+use crate::allocator::{Locked, LockedGuard};
+use crate::vga_buffer::Color;
+use core::fmt;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+/// Severity of a log message, from most to least severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum LogLevel {
+  /// Something failed
+  Error = 0,
+  /// Something unexpected happened but we can carry on
+  Warn = 1,
+  /// General progress of the kernel
+  Info = 2,
+  /// Details useful when debugging
+  Debug = 3,
+  /// Very verbose details
+  Trace = 4,
+}
+
+impl LogLevel {
+  fn from_u8(value: u8) -> LogLevel {
+    match value {
+      0 => LogLevel::Error,
+      1 => LogLevel::Warn,
+      2 => LogLevel::Info,
+      3 => LogLevel::Debug,
+      _ => LogLevel::Trace,
+    }
+  }
+
+  /// The tag printed in front of messages of this level.
+  fn tag(self) -> &'static str {
+    match self {
+      LogLevel::Error => "ERROR",
+      LogLevel::Warn => "WARN ",
+      LogLevel::Info => "INFO ",
+      LogLevel::Debug => "DEBUG",
+      LogLevel::Trace => "TRACE",
+    }
+  }
+
+  /// The color of the tag on the VGA buffer.
+  fn color(self) -> Color {
+    match self {
+      LogLevel::Error => Color::LightRed,
+      LogLevel::Warn => Color::Yellow,
+      LogLevel::Info => Color::LightGreen,
+      LogLevel::Debug => Color::LightCyan,
+      LogLevel::Trace => Color::DarkGray,
+    }
+  }
+}
+
+/// The least severe level that is still logged
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+/// Whether messages are prefixed with the uptime
+static TIMESTAMPS: AtomicBool = AtomicBool::new(false);
+/// Whether messages are also written to the serial port
+static MIRROR_TO_SERIAL: AtomicBool = AtomicBool::new(false);
+
+/// Only log messages at least as severe as `level` from now on.
+pub fn set_log_level(level: LogLevel) {
+  LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// The least severe level that is currently logged.
+pub fn log_level() -> LogLevel {
+  LogLevel::from_u8(LOG_LEVEL.load(Ordering::Relaxed))
+}
+
+/// Whether a message of `level` would be logged.
+pub fn enabled(level: LogLevel) -> bool {
+  level <= log_level()
+}
+
+/// Enable or disable prefixing every message with the time since boot.
+pub fn set_timestamps(timestamps: bool) {
+  TIMESTAMPS.store(timestamps, Ordering::Relaxed);
+}
+
+/// Enable or disable writing log messages to the serial port as well.
+///
+/// This is independent of [set_mirror](crate::vga_buffer::set_mirror),
+/// enabling both writes every message to the serial port twice.
+pub fn set_mirror(mirror: bool) {
+  MIRROR_TO_SERIAL.store(mirror, Ordering::Relaxed);
+}
+
+/// Log a message at the given level, e.g.,
+/// `log!(LogLevel::Info, "heap at {:#x}", start)`.
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => (
+      $crate::log::_log($level, format_args!($($arg)*))
+    );
+}
+
+/// Log a message at [LogLevel::Error](crate::log::LogLevel::Error)
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => ($crate::log!($crate::log::LogLevel::Error, $($arg)*));
+}
+
+/// Log a message at [LogLevel::Warn](crate::log::LogLevel::Warn)
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => ($crate::log!($crate::log::LogLevel::Warn, $($arg)*));
+}
+
+/// Log a message at [LogLevel::Info](crate::log::LogLevel::Info)
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => ($crate::log!($crate::log::LogLevel::Info, $($arg)*));
+}
+
+/// Log a message at [LogLevel::Debug](crate::log::LogLevel::Debug)
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => ($crate::log!($crate::log::LogLevel::Debug, $($arg)*));
+}
+
+/// Log a message at [LogLevel::Trace](crate::log::LogLevel::Trace)
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => ($crate::log!($crate::log::LogLevel::Trace, $($arg)*));
+}
+
+#[doc(hidden)]
+pub fn _log(level: LogLevel, args: fmt::Arguments) {
+  use core::fmt::Write;
+  use x86_64::instructions::interrupts;
+
+  if !enabled(level) {
+    return;
+  }
+
+  // keep the tag and the message together
+  interrupts::without_interrupts(|| {
+    let uptime = Uptime(crate::interrupts::uptime_ms());
+    let timestamps = TIMESTAMPS.load(Ordering::Relaxed);
+
+    if timestamps {
+      crate::print!("{} ", uptime);
+    }
+    crate::cprint!(level.color(), Color::Black, "[{}]", level.tag());
+    crate::println!(" {}", args);
+
+    if MIRROR_TO_SERIAL.load(Ordering::Relaxed) {
+      if timestamps {
+        crate::serial_print!("{} ", uptime);
+      }
+      crate::serial_println!("[{}] {}", level.tag(), args);
+    }
+
+    let mut buffer = LOG_BUFFER.lock();
+    if timestamps {
+      let _ = write!(buffer, "{} ", uptime);
+    }
+    let _ = writeln!(buffer, "[{}] {}", level.tag(), args);
+  });
+}
+
+/// Capacity of the in-memory log in bytes
+pub const LOG_BUFFER_SIZE: usize = 4096;
+
+/// The most recent log lines, see [dump]
+static LOG_BUFFER: Locked<RingBuffer<LOG_BUFFER_SIZE>> =
+  Locked::new(RingBuffer::new());
+
+/// A byte buffer of fixed capacity `N` that overwrites its oldest data
+/// when full.
+struct RingBuffer<const N: usize> {
+  buf: [u8; N],
+  /// Index of the oldest byte
+  start: usize,
+  /// Number of bytes stored
+  len: usize,
+  /// Whether any data has been overwritten
+  overwritten: bool,
+}
+
+impl<const N: usize> RingBuffer<N> {
+  const fn new() -> Self {
+    RingBuffer {
+      buf: [0; N],
+      start: 0,
+      len: 0,
+      overwritten: false,
+    }
+  }
+
+  fn push(&mut self, bytes: &[u8]) {
+    for &byte in bytes {
+      let end = (self.start + self.len) % N;
+      self.buf[end] = byte;
+      if self.len == N {
+        self.start = (self.start + 1) % N;
+        self.overwritten = true;
+      } else {
+        self.len += 1;
+      }
+    }
+  }
+
+  /// Move the stored bytes to the front of the buffer, oldest first, so
+  /// they can be read as one slice.
+  fn make_contiguous(&mut self) {
+    self.buf.rotate_left(self.start);
+    self.start = 0;
+  }
+
+  /// The stored bytes, only in order after [RingBuffer::make_contiguous]
+  fn as_slice(&self) -> &[u8] {
+    &self.buf[..self.len]
+  }
+
+  /// The stored complete lines, oldest first. A line whose beginning has
+  /// been overwritten is skipped.
+  fn lines(&self) -> impl Iterator<Item = &str> {
+    let mut data = self.as_slice();
+    if self.overwritten {
+      data = match data.iter().position(|&byte| byte == b'\n') {
+        Some(newline) => &data[newline + 1..],
+        None => &[],
+      };
+    }
+    data
+      .split(|&byte| byte == b'\n')
+      .filter(|line| !line.is_empty())
+      .map(|line| core::str::from_utf8(line).unwrap_or("<invalid utf-8>"))
+  }
+}
+
+impl<const N: usize> fmt::Write for RingBuffer<N> {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    self.push(s.as_bytes());
+    Ok(())
+  }
+}
+
+/// The contents of the in-memory log, returned by [dump].
+///
+/// This holds the log's lock and keeps interrupts disabled until it is
+/// dropped, so that a handler logging something can't deadlock on it.
+/// Don't hold on to it for long.
+pub struct LogDump {
+  guard: LockedGuard<'static, RingBuffer<LOG_BUFFER_SIZE>>,
+}
+
+impl LogDump {
+  /// Iterate over the logged lines, oldest first, without their trailing
+  /// newline. Once the buffer has wrapped around, the oldest, partly
+  /// overwritten line is left out.
+  pub fn lines(&self) -> impl Iterator<Item = &str> {
+    self.guard.lines()
+  }
+}
+
+impl Deref for LogDump {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    self.guard.as_slice()
+  }
+}
+
+/// Returns the most recently logged bytes, up to [LOG_BUFFER_SIZE] of
+/// them, oldest first. Older data has been overwritten.
+pub fn dump() -> LogDump {
+  let mut guard = LOG_BUFFER.lock();
+  guard.make_contiguous();
+  LogDump { guard }
+}
+
+/// Formats milliseconds since boot as `[seconds.millis]`.
+struct Uptime(u64);
+
+impl fmt::Display for Uptime {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "[{:>5}.{:03}]", self.0 / 1000, self.0 % 1000)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test_case]
+  fn test_log_level_filter() {
+    let old_level = log_level();
+
+    set_log_level(LogLevel::Warn);
+    assert!(enabled(LogLevel::Error));
+    assert!(enabled(LogLevel::Warn));
+    assert!(!enabled(LogLevel::Info));
+    assert!(!enabled(LogLevel::Trace));
+    crate::warn!("shown at level {:?}", log_level());
+    crate::debug!("filtered out");
+
+    set_log_level(LogLevel::Trace);
+    assert!(enabled(LogLevel::Trace));
+
+    set_log_level(old_level);
+  }
+
+  #[test_case]
+  fn test_dump_contains_message() {
+    crate::info!("dump test message");
+    let dump = dump();
+    assert_eq!(dump.lines().last(), Some("[INFO ] dump test message"));
+  }
+
+  #[test_case]
+  fn test_ring_buffer_overwrites_oldest() {
+    use alloc::vec::Vec;
+    use core::fmt::Write;
+
+    let mut ring = RingBuffer::<16>::new();
+    writeln!(ring, "first").unwrap();
+    writeln!(ring, "second").unwrap();
+    ring.make_contiguous();
+    assert_eq!(ring.as_slice(), b"first\nsecond\n");
+    assert_eq!(ring.lines().collect::<Vec<_>>(), ["first", "second"]);
+
+    writeln!(ring, "third").unwrap();
+    ring.make_contiguous();
+    assert_eq!(ring.as_slice(), b"st\nsecond\nthird\n");
+    assert_eq!(ring.lines().collect::<Vec<_>>(), ["second", "third"]);
+  }
+
+  #[test_case]
+  fn test_uptime_format() {
+    use alloc::format;
+
+    assert_eq!(format!("{}", Uptime(0)), "[    0.000]");
+    assert_eq!(format!("{}", Uptime(12_345)), "[   12.345]");
+  }
+}
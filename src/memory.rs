@@ -1,8 +1,11 @@
 //! This module contains the kernel's Virtual Memory Functionalities.
 
+use alloc::vec::Vec;
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
 use x86_64::structures::paging::{
-  FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PhysFrame, Size4KiB,
+  mapper::{MapToError, MappedFrame, UnmapError},
+  FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable,
+  PageTableFlags, PhysFrame, Size4KiB,
 };
 use x86_64::{PhysAddr, VirtAddr};
 
@@ -55,8 +58,9 @@ pub unsafe fn active_level4_page_table(
 // x86_64 virtual address format:
 // [63 - 48]        [47 - 39]    [38 - 30]    [29 - 21]    [20 - 12] .  [11 - 0]
 // [sign extention][page4 index][page3 index][page2 index][page1 index][offset in page]
-const IDX_MASK: u64 = 0b11111111;
+const IDX_MASK: u64 = 0x1ff;
 const OFFSET_MASK: u64 = 0xfff;
+const HUGE_PAGE_OFFSET_MASK: u64 = 0x1f_ffff;
 
 fn level4_page_table_index(addr: VirtAddr) -> u64 {
   let addr = addr.as_u64();
@@ -83,8 +87,23 @@ fn offset_in_page(addr: VirtAddr) -> u64 {
   addr & OFFSET_MASK
 }
 
+/// Translate a given [VirtAddr] to the mapped [PhysAddr] through `mapper`.
+///
+/// This handles pages of every size, and is what the rest of the kernel
+/// should use. [translate_virt_address] walks the tables by hand to show
+/// how translation works.
+pub fn translate(addr: VirtAddr, mapper: &OffsetPageTable) -> Option<PhysAddr> {
+  use x86_64::structures::paging::Translate;
+
+  mapper.translate_addr(addr)
+}
+
 /// Translate a given [VirtAddr] to the mapped [PhysAddr] by the process's page table.
 ///
+/// Returns `None` if the address is not mapped or is covered by a 1GiB page,
+/// see [translate_with_flags] for details. Prefer [translate] outside of
+/// examples and tests.
+///
 /// # Safety
 /// The given `physical_memory_offset` must be valid: the entire physical
 /// memory must be mapped by the offset.
@@ -95,6 +114,35 @@ pub unsafe fn translate_virt_address(
   addr: VirtAddr,
   physical_memory_offset: VirtAddr,
 ) -> Option<PhysAddr> {
+  match unsafe { translate_with_flags(addr, physical_memory_offset) }? {
+    Translation::Mapped(phys, _) => Some(phys),
+    Translation::HugePage(_) => None,
+  }
+}
+
+/// The result of a successful page table walk, see [translate_with_flags].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Translation {
+  /// The address is mapped by a 4KiB or 2MiB page with the given leaf
+  /// entry flags.
+  Mapped(PhysAddr, PageTableFlags),
+  /// The address is covered by a 1GiB page, whose entry has the given
+  /// flags. The physical address is not resolved.
+  HugePage(PageTableFlags),
+}
+
+/// Translate a given [VirtAddr] by the process's page table, returning the
+/// flags of the leaf entry along with the mapped [PhysAddr].
+///
+/// Returns `None` if the address is not mapped.
+///
+/// # Safety
+/// The given `physical_memory_offset` must be valid: the entire physical
+/// memory must be mapped by the offset.
+pub unsafe fn translate_with_flags(
+  addr: VirtAddr,
+  physical_memory_offset: VirtAddr,
+) -> Option<Translation> {
   use x86_64::registers::control::Cr3;
   use x86_64::structures::paging::page_table::FrameError;
 
@@ -107,30 +155,207 @@ pub unsafe fn translate_virt_address(
   ];
 
   let mut current_frame = level4_table_frame;
+  let mut flags = PageTableFlags::empty();
 
   // traverse the indexes
-  for &index in &indexes {
+  for (level, &index) in (1..=4).rev().zip(&indexes) {
     let virt = physical_memory_offset + current_frame.start_address().as_u64();
     let table_ptr: *const PageTable = virt.as_ptr();
     let table = unsafe { &*table_ptr };
 
     // read the index in the table
     let entry = &table[index as usize];
+    flags = entry.flags();
     current_frame = match entry.frame() {
       Ok(frame) => frame,
       Err(FrameError::FrameNotPresent) => return None,
-      Err(FrameError::HugeFrame) => panic!("huge pages not supported"),
+      // a 2MiB page, the low 21 bits of the address are the offset in it
+      Err(FrameError::HugeFrame) if level == 2 => {
+        let offset = addr.as_u64() & HUGE_PAGE_OFFSET_MASK;
+        return Some(Translation::Mapped(entry.addr() + offset, flags));
+      }
+      Err(FrameError::HugeFrame) => return Some(Translation::HugePage(flags)),
     }
   }
 
   // Calculate exact address with offset in page
-  Some(current_frame.start_address() + offset_in_page(addr))
+  Some(Translation::Mapped(
+    current_frame.start_address() + offset_in_page(addr),
+    flags,
+  ))
+}
+
+/// Returns an iterator over the present mappings of the active page table,
+/// ordered by virtual address. Each item is the first page of a mapping,
+/// the frame it maps to, whose size tells if it's a 4KiB, 2MiB or 1GiB
+/// page, and the flags of its leaf entry.
+///
+/// The tables are read through the physical memory offset of `mapper`.
+/// Entries that aren't present are skipped without looking at the tables
+/// below them, so the mostly empty address space is walked quickly.
+pub fn iter_mappings<'a>(mapper: &'a OffsetPageTable<'_>) -> Mappings<'a> {
+  use x86_64::registers::control::Cr3;
+
+  let phys_offset = mapper.phys_offset();
+  let (level4_table_frame, _) = Cr3::read();
+  let virt = phys_offset + level4_table_frame.start_address().as_u64();
+  // Safety: the mapper guarantees that physical memory is mapped at the
+  // offset, and we only read the tables
+  let level4_table = unsafe { &*virt.as_ptr::<PageTable>() };
+  Mappings {
+    phys_offset,
+    tables: [level4_table; 4],
+    next: [0; 4],
+    depth: 1,
+  }
+}
+
+/// The iterator returned by [iter_mappings]. It walks the page tables
+/// depth first, one entry at a time.
+pub struct Mappings<'a> {
+  phys_offset: VirtAddr,
+  /// The tables on the path to the current entry, level 4 first
+  tables: [&'a PageTable; 4],
+  /// Index of the next entry to look at in each of `tables`
+  next: [usize; 4],
+  /// Number of tables on the path
+  depth: usize,
+}
+
+impl<'a> Iterator for Mappings<'a> {
+  type Item = (Page, MappedFrame, PageTableFlags);
+
+  fn next(&mut self) -> Option<Self::Item> {
+    use x86_64::structures::paging::{Size1GiB, Size2MiB};
+
+    while self.depth > 0 {
+      // 0 for the level 4 table, 3 for a level 1 table
+      let depth = self.depth - 1;
+      let index = self.next[depth];
+      if index == 512 {
+        self.depth -= 1;
+        continue;
+      }
+      self.next[depth] += 1;
+
+      let entry = &self.tables[depth][index];
+      let flags = entry.flags();
+      if !flags.contains(PageTableFlags::PRESENT) {
+        continue;
+      }
+
+      let huge = depth > 0 && flags.contains(PageTableFlags::HUGE_PAGE);
+      if depth < 3 && !huge {
+        let virt = self.phys_offset + entry.addr().as_u64();
+        // Safety: see iter_mappings
+        self.tables[depth + 1] = unsafe { &*virt.as_ptr::<PageTable>() };
+        self.next[depth + 1] = 0;
+        self.depth += 1;
+        continue;
+      }
+
+      // the entries on the path make up the page's indexes, the ones of
+      // levels below a huge page are 0
+      let mut addr = 0;
+      for (level, &next) in self.next[..=depth].iter().enumerate() {
+        addr |= (next as u64 - 1) << (39 - 9 * level);
+      }
+      let page = Page::containing_address(VirtAddr::new_truncate(addr));
+      let frame = match depth {
+        1 => MappedFrame::Size1GiB(PhysFrame::<Size1GiB>::containing_address(
+          entry.addr(),
+        )),
+        2 => MappedFrame::Size2MiB(PhysFrame::<Size2MiB>::containing_address(
+          entry.addr(),
+        )),
+        _ => MappedFrame::Size4KiB(PhysFrame::containing_address(entry.addr())),
+      };
+      return Some((page, frame, flags));
+    }
+    None
+  }
+}
+
+/// Translate a given [VirtAddr] to the mapped [PhysAddr] by the process's
+/// page table, accessing the tables through a recursive mapping instead of
+/// the physical memory offset.
+///
+/// Entry `recursive_index` of the level 4 table must point to the level 4
+/// table itself. Following that entry once or more often before the regular
+/// indexes of `addr` then ends up in the table one level up, e.g. the
+/// address made of the indexes `[recursive_index; 4]` is the level 4 table.
+///
+/// Returns `None` if the address is not mapped or is covered by a 1GiB page.
+///
+/// # Safety
+/// The level 4 entry at `recursive_index` must be a recursive entry as
+/// described above.
+///
+/// Panics if `recursive_index` is not a valid index, i.e. at least 512.
+pub unsafe fn translate_recursive(
+  addr: VirtAddr,
+  recursive_index: u16,
+) -> Option<PhysAddr> {
+  use x86_64::structures::paging::page_table::FrameError;
+
+  assert!(
+    recursive_index < 512,
+    "invalid recursive index {}",
+    recursive_index
+  );
+  let r = u64::from(recursive_index);
+  let indexes = [
+    level4_page_table_index(addr),
+    level3_page_table_index(addr),
+    level2_page_table_index(addr),
+    level1_page_table_index(addr),
+  ];
+
+  // The table of `level` is reached by following the recursive entry
+  // `level` times and then the first `4 - level` indexes of `addr`.
+  let table_addr = |level: usize| {
+    let mut table_indexes = [r; 4];
+    table_indexes[level..].copy_from_slice(&indexes[..4 - level]);
+    let [i4, i3, i2, i1] = table_indexes;
+    VirtAddr::new_truncate(i4 << 39 | i3 << 30 | i2 << 21 | i1 << 12)
+  };
+
+  for (level, &index) in (1..=4).rev().zip(&indexes) {
+    let table_ptr: *const PageTable = table_addr(level).as_ptr();
+    let entry = &unsafe { &*table_ptr }[index as usize];
+
+    match entry.frame() {
+      Ok(frame) if level == 1 => {
+        return Some(frame.start_address() + offset_in_page(addr));
+      }
+      Ok(_) => {}
+      Err(FrameError::FrameNotPresent) => return None,
+      Err(FrameError::HugeFrame) if level == 2 => {
+        return Some(entry.addr() + (addr.as_u64() & HUGE_PAGE_OFFSET_MASK));
+      }
+      Err(FrameError::HugeFrame) => return None,
+    }
+  }
+  unreachable!("the level 1 entry either maps a frame or is not present")
 }
 
 /// A FrameAllocator that returns usable frames from the bootloader's memory map.
+///
+/// Frames are handed out in memory map order. The allocator remembers
+/// the region it is in and the next address within it, so allocating a
+/// frame is amortized O(1).
+///
+/// Deallocated frames are kept in a heap-allocated free list and reused
+/// first. Frames can thus only be deallocated after the heap is
+/// initialized, allocating works at any time.
 pub struct BootInfoFrameAllocator {
   memory_map: &'static MemoryMap,
-  next: usize,
+  // frames returned through deallocate_frame
+  free_list: Vec<PhysFrame>,
+  // index of the memory region to allocate from
+  region: usize,
+  // start address of the next frame to allocate in that region
+  next_addr: u64,
 }
 
 impl BootInfoFrameAllocator {
@@ -145,38 +370,295 @@ impl BootInfoFrameAllocator {
   pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
     BootInfoFrameAllocator {
       memory_map,
-      next: 0,
+      free_list: Vec::new(),
+      region: 0,
+      next_addr: 0,
     }
   }
-}
 
-impl BootInfoFrameAllocator {
-  /// Returns an iterator over the usable frames specified in the memory map.
-  fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-    // get usable regions from memory map
-    let regions = self.memory_map.iter();
-    let usable_regions =
-      regions.filter(|r| r.region_type == MemoryRegionType::Usable);
-    // map each region to its address range
-    let addr_ranges =
-      usable_regions.map(|r| r.range.start_addr()..r.range.end_addr());
-    // transform to an iterator of frame start addresses
-    // the ranges are already page-aligned, so we're guaranteed to have valid page-start addresses
-    let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
-    // create `PhysFrame` types from the start addresses
-    frame_addresses
-      .map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+  /// Number of returned frames waiting to be handed out again.
+  pub fn free_frames(&self) -> usize {
+    self.free_list.len()
+  }
+
+  /// Whether the frame at `addr` is still to be handed out from the
+  /// memory map, i.e. it lies at or after the next frame to allocate.
+  /// Regions are sorted by address, so this covers later regions too.
+  fn not_yet_allocated(&self, addr: u64) -> bool {
+    match self.memory_map.get(self.region) {
+      Some(region) => addr >= self.next_addr.max(region.range.start_addr()),
+      None => false,
+    }
   }
 }
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
   fn allocate_frame(&mut self) -> Option<PhysFrame> {
-    let frame = self.usable_frames().nth(self.next);
-    self.next += 1;
-    frame
+    if let Some(frame) = self.free_list.pop() {
+      return Some(frame);
+    }
+
+    while let Some(region) = self.memory_map.get(self.region) {
+      if region.region_type == MemoryRegionType::Usable {
+        // the ranges are already page-aligned, so we're guaranteed to
+        // have valid page-start addresses
+        let addr = self.next_addr.max(region.range.start_addr());
+        if addr < region.range.end_addr() {
+          self.next_addr = addr + 4096;
+          return Some(PhysFrame::containing_address(PhysAddr::new(addr)));
+        }
+      }
+      self.region += 1;
+      self.next_addr = 0;
+    }
+    None
+  }
+}
+
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+  /// Return the frame to the free list, or give it back to the region it
+  /// came from if it's the frame allocated last.
+  ///
+  /// Deallocating a frame twice, or one that was never allocated, is a
+  /// bug that would hand the frame out twice. It fails an assertion in
+  /// debug builds, and the frame is ignored in release builds.
+  ///
+  /// The heap must be initialized, as the free list is heap-allocated.
+  unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+    let addr = frame.start_address().as_u64();
+    // this also catches a frame that was given back to its region already
+    let unallocated = self.not_yet_allocated(addr);
+    let double_free = unallocated || self.free_list.contains(&frame);
+    debug_assert!(!double_free, "frame {:?} deallocated twice", frame);
+    if double_free {
+      return;
+    }
+
+    if self.next_addr != 0 && addr + 4096 == self.next_addr {
+      self.next_addr = addr;
+    } else {
+      self.free_list.push(frame);
+    }
   }
 }
 
+/// Map `count` contiguous pages starting at `start_page`, each to a
+/// freshly allocated frame, with the given flags.
+///
+/// The caller picks the flags, e.g. `PRESENT | WRITABLE` for plain
+/// memory or with `NO_CACHE` added for memory accessed by devices.
+pub fn map_range(
+  start_page: Page,
+  count: u64,
+  flags: PageTableFlags,
+  mapper: &mut impl Mapper<Size4KiB>,
+  frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+  for page in Page::range(start_page, start_page + count) {
+    let frame = frame_allocator
+      .allocate_frame()
+      .ok_or(MapToError::FrameAllocationFailed)?;
+    unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+  }
+
+  Ok(())
+}
+
+/// Map `page` to the device memory at `frame`, e.g. a linear framebuffer
+/// or memory-mapped registers.
+///
+/// Plain mappings are cacheable, so writes could linger in the cache
+/// and reads could return stale data instead of reaching the device. The
+/// page is therefore mapped with `NO_CACHE` and `WRITE_THROUGH`, which
+/// selects the uncacheable memory type with the default PAT. It is also
+/// mapped `NO_EXECUTE`, as device memory never holds code.
+///
+/// Write-combining, which suits framebuffers best, would need the PAT to
+/// be reprogrammed and is not supported.
+///
+/// # Safety
+/// The caller must guarantee that `frame` is device memory that isn't
+/// handed out by any frame allocator, and that `page` is unused.
+pub unsafe fn map_mmio(
+  page: Page,
+  frame: PhysFrame,
+  mapper: &mut impl Mapper<Size4KiB>,
+  frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+  let flags = PageTableFlags::PRESENT
+    | PageTableFlags::WRITABLE
+    | PageTableFlags::NO_CACHE
+    | PageTableFlags::WRITE_THROUGH
+    | PageTableFlags::NO_EXECUTE;
+  unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+  Ok(())
+}
+
+/// Remove the mapping of the given page and return the frame it was
+/// mapped to.
+///
+/// The stale translation may still be cached in the TLB, so the page is
+/// flushed before returning. The caller is responsible for the frame,
+/// see [unmap_and_free_page] to hand it back to a frame deallocator.
+pub fn unmap_page(
+  page: Page,
+  mapper: &mut impl Mapper<Size4KiB>,
+) -> Result<PhysFrame, UnmapError> {
+  let (frame, flush) = mapper.unmap(page)?;
+  flush.flush();
+  Ok(frame)
+}
+
+/// Remove the mapping of the given page, like [unmap_page], and return
+/// the frame it was mapped to to `frame_deallocator` for reuse.
+///
+/// # Safety
+/// The caller must guarantee that the frame is not used through any
+/// other mapping.
+pub unsafe fn unmap_and_free_page(
+  page: Page,
+  mapper: &mut impl Mapper<Size4KiB>,
+  frame_deallocator: &mut impl FrameDeallocator<Size4KiB>,
+) -> Result<(), UnmapError> {
+  let frame = unmap_page(page, mapper)?;
+  unsafe { frame_deallocator.deallocate_frame(frame) };
+  Ok(())
+}
+
+/// Region types counted by [memory_report], in the order they are listed.
+const REGION_TYPES: [MemoryRegionType; 14] = [
+  MemoryRegionType::Usable,
+  MemoryRegionType::InUse,
+  MemoryRegionType::Reserved,
+  MemoryRegionType::AcpiReclaimable,
+  MemoryRegionType::AcpiNvs,
+  MemoryRegionType::BadMemory,
+  MemoryRegionType::Kernel,
+  MemoryRegionType::KernelStack,
+  MemoryRegionType::PageTable,
+  MemoryRegionType::Bootloader,
+  MemoryRegionType::FrameZero,
+  MemoryRegionType::Empty,
+  MemoryRegionType::BootInfo,
+  MemoryRegionType::Package,
+];
+
+/// How much physical memory the boot memory map assigns to each region
+/// type, see [memory_report].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryReport {
+  /// Bytes of each type, indexed like [REGION_TYPES]
+  bytes: [u64; REGION_TYPES.len()],
+  /// Number of 4KiB frames covered by the memory map
+  pub total_frames: u64,
+}
+
+impl MemoryReport {
+  /// Bytes of memory of the given type.
+  pub fn bytes(&self, region_type: MemoryRegionType) -> u64 {
+    REGION_TYPES
+      .iter()
+      .position(|&t| t == region_type)
+      .map_or(0, |i| self.bytes[i])
+  }
+
+  /// Bytes covered by the memory map.
+  pub fn total_bytes(&self) -> u64 {
+    self.total_frames * 4096
+  }
+}
+
+/// Lists the total followed by every region type that has any memory.
+impl core::fmt::Display for MemoryReport {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    write!(
+      f,
+      "{} in {} frames",
+      ByteSize(self.total_bytes()),
+      self.total_frames
+    )?;
+    for (region_type, &bytes) in REGION_TYPES.iter().zip(&self.bytes) {
+      if bytes != 0 {
+        write!(f, "\n  {:?}: {}", region_type, ByteSize(bytes))?;
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Formats a number of bytes in the largest binary unit that fits, with
+/// one decimal, e.g. `1.5 MiB`.
+struct ByteSize(u64);
+
+impl core::fmt::Display for ByteSize {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    const UNITS: [&str; 3] = ["GiB", "MiB", "KiB"];
+
+    for (i, unit) in UNITS.iter().enumerate() {
+      let unit_size = 1u64 << (10 * (UNITS.len() - i));
+      if self.0 >= unit_size {
+        let tenths = self.0 * 10 / unit_size;
+        return write!(f, "{}.{} {}", tenths / 10, tenths % 10, unit);
+      }
+    }
+    write!(f, "{} B", self.0)
+  }
+}
+
+/// Summarize how much physical memory `memory_map` assigns to each region
+/// type, e.g. to see how much RAM the machine has.
+pub fn memory_report(memory_map: &MemoryMap) -> MemoryReport {
+  let mut report = MemoryReport {
+    bytes: [0; REGION_TYPES.len()],
+    total_frames: 0,
+  };
+  for region in memory_map.iter() {
+    let size = region.range.end_addr() - region.range.start_addr();
+    report.total_frames +=
+      region.range.end_frame_number - region.range.start_frame_number;
+    if let Some(i) = REGION_TYPES.iter().position(|&t| t == region.region_type)
+    {
+      report.bytes[i] += size;
+    }
+  }
+  report
+}
+
+/// Returns the start and length in bytes of the largest contiguous range
+/// of usable physical memory in `memory_map`, or `None` if nothing is
+/// usable. Usable regions that directly follow each other count as one
+/// range.
+///
+/// This is where physically contiguous memory, e.g. the frames backing
+/// the heap, is least likely to be split across the memory map.
+pub fn largest_usable_region(
+  memory_map: &MemoryMap,
+) -> Option<(PhysAddr, u64)> {
+  let mut largest: Option<(u64, u64)> = None;
+  // the usable range we're currently in, the map is sorted by address
+  let mut current: Option<(u64, u64)> = None;
+  for region in memory_map.iter() {
+    if region.region_type != MemoryRegionType::Usable {
+      current = None;
+      continue;
+    }
+    let (start, end) = (region.range.start_addr(), region.range.end_addr());
+    current = match current {
+      Some((current_start, current_end)) if current_end == start => {
+        Some((current_start, end))
+      }
+      _ => Some((start, end)),
+    };
+
+    let (current_start, current_end) = current.unwrap();
+    let current_len = current_end - current_start;
+    if largest.is_none_or(|(_, len)| current_len > len) {
+      largest = Some((current_start, current_len));
+    }
+  }
+  largest.map(|(start, len)| (PhysAddr::new(start), len))
+}
+
 // Experimental Functions and Structures for concept demonstration
 
 /// A FrameAllocator that always returns `None`.
@@ -189,6 +671,11 @@ unsafe impl FrameAllocator<Size4KiB> for EmptyFrameAllocator {
 }
 
 /// Creates an example mapping for the given page to frame `0xb8000`.
+///
+/// Even though this is device memory, a cacheable mapping works here: the
+/// firmware marks the legacy VGA range `0xa0000..0xc0000` as uncacheable in
+/// the MTRRs, which take precedence over the page flags. Other device
+/// memory should be mapped with [map_mmio].
 pub fn create_example_mapping(
   page: Page,
   mapper: &mut OffsetPageTable,
@@ -210,8 +697,110 @@ pub fn create_example_mapping(
 mod tests {
   use super::*;
 
+  #[test_case]
+  fn test_byte_size() {
+    use alloc::format;
+
+    assert_eq!(format!("{}", ByteSize(512)), "512 B");
+    assert_eq!(format!("{}", ByteSize(4096)), "4.0 KiB");
+    assert_eq!(format!("{}", ByteSize(3 << 19)), "1.5 MiB");
+    assert_eq!(format!("{}", ByteSize(2 << 30)), "2.0 GiB");
+  }
+
+  #[test_case]
+  fn test_largest_usable_region() {
+    use bootloader::bootinfo::{FrameRange, MemoryRegion};
+
+    let mut memory_map = MemoryMap::new();
+    assert_eq!(largest_usable_region(&memory_map), None);
+
+    for &(start, end, region_type) in &[
+      (0x0000, 0x9000, MemoryRegionType::Usable),
+      (0x9000, 0xa000, MemoryRegionType::Reserved),
+      // two adjacent usable regions, together larger than the first one
+      (0x10_0000, 0x10_5000, MemoryRegionType::Usable),
+      (0x10_5000, 0x10_a000, MemoryRegionType::Usable),
+      (0x10_a000, 0x10_b000, MemoryRegionType::Kernel),
+      (0x20_0000, 0x20_2000, MemoryRegionType::Usable),
+    ] {
+      memory_map.add_region(MemoryRegion {
+        range: FrameRange::new(start, end),
+        region_type,
+      });
+    }
+
+    assert_eq!(
+      largest_usable_region(&memory_map),
+      Some((PhysAddr::new(0x10_0000), 0xa000))
+    );
+  }
+
+  /// A frame allocator over a made-up memory map of four frames. The
+  /// frames are never accessed, so they don't need to exist.
+  fn test_frame_allocator() -> BootInfoFrameAllocator {
+    use alloc::boxed::Box;
+    use bootloader::bootinfo::{FrameRange, MemoryRegion};
+
+    let mut memory_map = MemoryMap::new();
+    memory_map.add_region(MemoryRegion {
+      range: FrameRange::new(0x10_0000, 0x10_4000),
+      region_type: MemoryRegionType::Usable,
+    });
+    unsafe { BootInfoFrameAllocator::init(Box::leak(Box::new(memory_map))) }
+  }
+
+  #[test_case]
+  fn test_deallocate_last_frame_rolls_back() {
+    let mut frames = test_frame_allocator();
+    let first = frames.allocate_frame().unwrap();
+    let second = frames.allocate_frame().unwrap();
+
+    // the frame allocated last goes back to its region
+    unsafe { frames.deallocate_frame(second) };
+    assert_eq!(frames.free_frames(), 0);
+    assert_eq!(frames.allocate_frame(), Some(second));
+
+    // any other frame goes to the free list, and is handed out first
+    unsafe { frames.deallocate_frame(first) };
+    assert_eq!(frames.free_frames(), 1);
+    assert_eq!(frames.allocate_frame(), Some(first));
+    assert_eq!(
+      frames.allocate_frame(),
+      Some(PhysFrame::containing_address(PhysAddr::new(0x10_2000)))
+    );
+  }
+
+  #[test_case]
+  fn test_deallocate_frame_twice() {
+    use core::cell::RefCell;
+
+    let mut frames = test_frame_allocator();
+    let frame = frames.allocate_frame().unwrap();
+    // rolls back, after which the frame counts as not yet allocated
+    unsafe { frames.deallocate_frame(frame) };
+
+    let frames = RefCell::new(frames);
+    let free_again = || unsafe { frames.borrow_mut().deallocate_frame(frame) };
+    #[cfg(debug_assertions)]
+    crate::test_harness::should_panic(free_again, "deallocated twice");
+    #[cfg(not(debug_assertions))]
+    free_again();
+    // the panic skipped releasing the borrow, which into_inner ignores
+    let mut frames = frames.into_inner();
+
+    // the frame is handed out only once
+    assert_eq!(frames.free_frames(), 0);
+    assert_eq!(frames.allocate_frame(), Some(frame));
+    assert_ne!(frames.allocate_frame(), Some(frame));
+  }
+
   #[test_case]
   fn test_page_index() {
+    // every index is 9 bits wide
+    let addr = VirtAddr::new(0x_7fff_ffff_ffff);
+    assert_eq!(level4_page_table_index(addr), 511);
+    assert_eq!(level1_page_table_index(addr), 511);
+
     let addr = VirtAddr::new(0xdeadbeaf);
     assert_eq!(u64::from(addr.p1_index()), level1_page_table_index(addr));
     assert_eq!(u64::from(addr.p2_index()), level2_page_table_index(addr));
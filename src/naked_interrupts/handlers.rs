@@ -11,6 +11,7 @@ pub extern "C" fn divide_by_zero_handler(frame: &ExceptionStackFrame) -> ! {
 
 /// Break point handler
 pub extern "C" fn breakpoint_handler(frame: &ExceptionStackFrame) {
+  crate::interrupts::handle_breakpoint();
   println!("EXCEPTION: BREAKPOINT");
   println!("{:#?}", frame);
 }
@@ -43,9 +44,33 @@ pub extern "C" fn double_fault_handler(
   panic!("EXCEPTION: DOUBLE_FAULT\n{:#?}", frame);
 }
 
+/// Timer interrupt handler
+pub extern "C" fn timer_interrupt_handler(_frame: &ExceptionStackFrame) {
+  crate::interrupts::handle_timer();
+}
+
+/// Keyboard interrupt handler
+pub extern "C" fn keyboard_interrupt_handler(_frame: &ExceptionStackFrame) {
+  crate::interrupts::handle_keyboard();
+}
+
+/// System call handler, the call number and argument are the values of
+/// `rax` and `rdi` when `int 0x80` was executed. See [syscall_handler!].
+pub extern "C" fn syscall_handler(
+  _frame: &ExceptionStackFrame,
+  number: u64,
+  arg: u64,
+) -> u64 {
+  crate::interrupts::handle_syscall(number, arg)
+}
+
 /// This macro wraps a fn(&ExceptionFrame) in the naked function that
 /// handles argument passing and raw stack manipulations, producing an fn() -> !
 /// to use in the Interrupt Descripter Table [super::idt::Idt]
+///
+/// All 15 general purpose registers are saved, so that the interrupted
+/// code sees them unchanged no matter what the handler does. There is no
+/// SSE state to save, as the kernel target is built with `-sse,+soft-float`.
 #[macro_export]
 macro_rules! handler {
   ($name: ident) => {{
@@ -53,33 +78,47 @@ macro_rules! handler {
     extern "C" fn wrapper() -> ! {
       unsafe {
         core::arch::asm!("
-          // safe all registers
+          // save all general purpose registers
           push rax;
+          push rbx;
           push rcx;
           push rdx;
           push rsi;
           push rdi;
+          push rbp;
           push r8;
           push r9;
           push r10;
           push r11;
+          push r12;
+          push r13;
+          push r14;
+          push r15;
 
           // calculate the address of the stack frame
           mov rdi, rsp;
-          add rdi, 9*8;
+          add rdi, 15*8;
 
-          // call handler functions
+          // the cpu aligns the stack to 16 bytes before pushing the
+          // 5 * 8 bytes stack frame, together with the 15 * 8 bytes
+          // registers the stack is aligned again for the call
           call {};
 
           // restore all registers
+          pop r15;
+          pop r14;
+          pop r13;
+          pop r12;
           pop r11;
           pop r10;
           pop r9;
           pop r8;
+          pop rbp;
           pop rdi;
           pop rsi;
           pop rdx;
           pop rcx;
+          pop rbx;
           pop rax;
 
           // return from exception handler
@@ -92,6 +131,8 @@ macro_rules! handler {
 
 /// This macro wraps a fn(&ExceptionFrame, u64) -> ! in the naked function, which
 /// handles CPU exceptions with an error code.
+///
+/// Like [handler!], all general purpose registers are saved.
 #[macro_export]
 macro_rules! handler_with_err_code {
     ($name: ident) => {{
@@ -101,29 +142,36 @@ macro_rules! handler_with_err_code {
           // pop error code into rsi, as error code is the lowest item
           // on the stack
           core::arch::asm!("
-            // save all registers
+            // save all general purpose registers
             push rax;
+            push rbx;
             push rcx;
             push rdx;
             push rsi;
             push rdi;
+            push rbp;
             push r8;
             push r9;
             push r10;
             push r11;
+            push r12;
+            push r13;
+            push r14;
+            push r15;
 
             // rsi should store the error code,
-            // which locates at memory address (rsp + 9 * 8)
+            // which locates at memory address (rsp + 15 * 8)
             mov rsi, rsp;
-            add rsi, 9 * 8;
+            add rsi, 15 * 8;
             mov rsi, [rsi];
 
             // rdi stores the address of the stack frame,
-            // which is rsp + 10 * 8
+            // which is rsp + 16 * 8
             mov rdi, rsp;
-            add rdi, 10 * 8;
+            add rdi, 16 * 8;
 
-            // align stack pointer
+            // align stack pointer, the stack frame, the error code and
+            // the registers add up to 21 * 8 bytes
             sub rsp, 8;
 
             call {};
@@ -132,14 +180,20 @@ macro_rules! handler_with_err_code {
             add rsp, 8;
 
             // restore all registers
+            pop r15;
+            pop r14;
+            pop r13;
+            pop r12;
             pop r11;
             pop r10;
             pop r9;
             pop r8;
+            pop rbp;
             pop rdi;
             pop rsi;
             pop rdx;
             pop rcx;
+            pop rbx;
             pop rax;
 
             // pop error code
@@ -153,3 +207,70 @@ macro_rules! handler_with_err_code {
       wrapper
     }};
 }
+
+/// This macro wraps a fn(&ExceptionFrame, u64, u64) -> u64 in the naked
+/// function, which handles system calls. The handler gets the interrupted
+/// code's `rax` and `rdi` as arguments, and its result is returned in `rax`.
+///
+/// Like [handler!], all other general purpose registers are saved.
+#[macro_export]
+macro_rules! syscall_handler {
+  ($name: ident) => {{
+    #[naked]
+    extern "C" fn wrapper() -> ! {
+      unsafe {
+        core::arch::asm!("
+          // save all general purpose registers
+          push rax;
+          push rbx;
+          push rcx;
+          push rdx;
+          push rsi;
+          push rdi;
+          push rbp;
+          push r8;
+          push r9;
+          push r10;
+          push r11;
+          push r12;
+          push r13;
+          push r14;
+          push r15;
+
+          // the call number and argument, from the original rax and rdi
+          mov rsi, rax;
+          mov rdx, rdi;
+
+          // calculate the address of the stack frame
+          mov rdi, rsp;
+          add rdi, 15*8;
+
+          // the stack is aligned the same way as in handler!
+          call {};
+
+          // overwrite the saved rax with the result
+          mov [rsp + 14*8], rax;
+
+          // restore all registers
+          pop r15;
+          pop r14;
+          pop r13;
+          pop r12;
+          pop r11;
+          pop r10;
+          pop r9;
+          pop r8;
+          pop rbp;
+          pop rdi;
+          pop rsi;
+          pop rdx;
+          pop rcx;
+          pop rbx;
+          pop rax;
+
+          iretq", sym $name, options(noreturn));
+      }
+    }
+    wrapper
+  }}
+}
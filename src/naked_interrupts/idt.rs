@@ -6,9 +6,8 @@ use x86_64::registers::segmentation::Segment;
 use x86_64::structures::gdt::SegmentSelector;
 use x86_64::PrivilegeLevel;
 
-/// IDT is just an array of IDE entries. Technically
-/// it can contain up to 256 entries, but we're only using
-/// the first 16, which are:
+/// IDT is just an array of IDE entries, one for each of the 256
+/// interrupt vectors. The first 16 are CPU exceptions:
 /// 0 -> Divide by zero
 /// 1 -> Debug
 /// 2 -> Non-maskable interrupt
@@ -25,13 +24,15 @@ use x86_64::PrivilegeLevel;
 /// 13 -> General protection fault
 /// 14 -> Page fault
 /// 15 -> Reserved
+/// Vectors from 32 onwards are used by hardware interrupts, e.g.
+/// 32 -> Timer and 33 -> Keyboard from the PIC.
 /// See https://wiki.osdev.org/Exceptions for detailed reference
-pub struct Idt([Entry; 16]);
+pub struct Idt([Entry; 256]);
 
 impl Idt {
   /// Create a default IDT with all missing entries
   pub fn new() -> Self {
-    Self([Entry::missing(); 16])
+    Self([Entry::missing(); 256])
   }
 
   /// Set handler function to the nth entry,
@@ -49,6 +50,28 @@ impl Idt {
     ptr
   }
 
+  /// Set handler function to the nth entry, switching to the interrupt
+  /// stack `ist_index` of the TSS when it's invoked.
+  ///
+  /// `ist_index` is the index into the TSS's interrupt stack table, e.g.
+  /// [crate::gdt::DOUBLE_FAULT_IST_INDEX]. The entry stores it plus 1, as 0
+  /// means not to switch stacks.
+  pub fn set_handler_with_stack(
+    &mut self,
+    entry: u8,
+    handler: HandlerFunc,
+    ist_index: u16,
+  ) {
+    let opt = self.set_handler(entry, handler);
+    // the pointer comes from a live entry of self, but the entry is
+    // packed, so it has to be accessed unaligned
+    unsafe {
+      let mut new_opt = opt.read_unaligned();
+      new_opt.set_stack_index(ist_index + 1);
+      opt.write_unaligned(new_opt);
+    }
+  }
+
   /// Load the current IDT for cpu to use
   /// * `&'static self` we need self to live for the whole lifetime of the program.
   /// Otherwise, cpu might read freed memory where it thinks the IDT resides.
@@ -209,6 +232,32 @@ pub type HandlerFunc = extern "C" fn() -> !;
 mod tests {
   use super::*;
 
+  extern "C" fn dummy_handler() -> ! {
+    crate::hlt_loop()
+  }
+
+  #[test_case]
+  fn test_full_table() {
+    // the limit must still fit in a u16
+    assert_eq!(core::mem::size_of::<Idt>() - 1, 4095);
+
+    let mut idt = Idt::new();
+    idt.set_handler(255, dummy_handler);
+    let entry = idt.0[255];
+    let pointer = entry.pointer_low as u64
+      | (entry.pointer_middle as u64) << 16
+      | (entry.pointer_high as u64) << 32;
+    assert_eq!(pointer, dummy_handler as HandlerFunc as usize as u64);
+  }
+
+  #[test_case]
+  fn test_set_handler_with_stack() {
+    let mut idt = Idt::new();
+    idt.set_handler_with_stack(8, dummy_handler, 0);
+    let options = idt.0[8].options;
+    assert_eq!(options.0 & 0b111, 1);
+  }
+
   #[test_case]
   fn test_default() {
     let opt = EntryOptions::minimal();
@@ -222,6 +271,16 @@ mod tests {
     assert_eq!(0b0000111000000111, opt.0);
   }
 
+  #[test_case]
+  fn test_invalid_stack_index() {
+    crate::test_harness::should_panic(
+      || {
+        EntryOptions::minimal().set_stack_index(8);
+      },
+      "Invalid stack index 8",
+    );
+  }
+
   #[test_case]
   fn test_set_present() {
     let mut opt = EntryOptions::minimal();
@@ -9,10 +9,13 @@ mod idt;
 
 pub(crate) use frame::ExceptionStackFrame;
 
-use crate::{handler, handler_with_err_code};
+use crate::interrupts::InterruptIndex;
+use crate::interrupts::SYSCALL_VECTOR;
+use crate::{handler, handler_with_err_code, syscall_handler};
 use handlers::{
   breakpoint_handler, divide_by_zero_handler, double_fault_handler,
-  invalid_opcode_handler, page_fault_handler,
+  invalid_opcode_handler, keyboard_interrupt_handler, page_fault_handler,
+  syscall_handler, timer_interrupt_handler,
 };
 
 lazy_static! {
@@ -23,18 +26,67 @@ lazy_static! {
     idt.set_handler(3, handler!(breakpoint_handler));
     idt.set_handler(6, handler!(invalid_opcode_handler));
     idt.set_handler(14, handler_with_err_code!(page_fault_handler));
-    unsafe {
-      let opt = idt.set_handler(8, handler_with_err_code!(double_fault_handler));
-      let mut new_opt = opt.read_unaligned();
-      // the index is the array index, and should be added 1 for the option
-      new_opt.set_stack_index(crate::gdt::DOUBLE_FAULT_IST_INDEX + 1);
-      opt.write_unaligned(new_opt);
-    }
+    idt.set_handler(
+      InterruptIndex::Timer.as_u8(),
+      handler!(timer_interrupt_handler),
+    );
+    idt.set_handler(
+      InterruptIndex::Keyboard.as_u8(),
+      handler!(keyboard_interrupt_handler),
+    );
+    // ring 3 may only raise interrupts whose gate has a privilege level
+    // of 3, otherwise `int` itself raises a general protection fault
+    let options = idt.set_handler(SYSCALL_VECTOR, syscall_entry());
+    unsafe { (*options).set_privilege_level(3) };
+    idt.set_handler_with_stack(
+      8,
+      handler_with_err_code!(double_fault_handler),
+      crate::gdt::DOUBLE_FAULT_IST_INDEX,
+    );
     idt
   };
 }
 
+/// The naked system call entry, which passes the caller's `rax` and `rdi`
+/// to [handle_syscall](crate::interrupts::handle_syscall) and returns its
+/// result in `rax`. The IDT of [crate::interrupts] installs it as well.
+pub(crate) fn syscall_entry() -> idt::HandlerFunc {
+  syscall_handler!(syscall_handler)
+}
+
 /// Initialize IDT
 pub fn init_idt() {
   IDT.load();
 }
+
+#[cfg(all(test, feature = "naked"))]
+mod tests {
+  use core::arch::asm;
+
+  #[test_case]
+  fn test_breakpoint_preserves_registers() {
+    let values: [u64; 13] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13];
+    let mut regs = values;
+    // rbx and rbp can't be used as operands, but they are saved the
+    // same way as the rest
+    unsafe {
+      asm!(
+        "int3",
+        inout("rax") regs[0],
+        inout("rcx") regs[1],
+        inout("rdx") regs[2],
+        inout("rsi") regs[3],
+        inout("rdi") regs[4],
+        inout("r8") regs[5],
+        inout("r9") regs[6],
+        inout("r10") regs[7],
+        inout("r11") regs[8],
+        inout("r12") regs[9],
+        inout("r13") regs[10],
+        inout("r14") regs[11],
+        inout("r15") regs[12],
+      );
+    }
+    assert_eq!(regs, values);
+  }
+}
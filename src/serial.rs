@@ -4,27 +4,150 @@
 use lazy_static::lazy_static;
 use spin::Mutex;
 use uart_16550::SerialPort;
+use x86_64::instructions::port::Port;
+
+/// The standard I/O port base of the first serial port
+pub const COM1: u16 = 0x3F8;
+/// The standard I/O port base of the second serial port
+pub const COM2: u16 = 0x2F8;
+
+/// The UART's base clock divided by 16. The baud rate is this frequency
+/// divided by the divisor programmed into the DLL/DLM registers.
+const UART_MAX_BAUD: u32 = 115200;
 
 lazy_static! {
   /// Global Serial Port
   pub static ref SERIAL1: Mutex<SerialPort> = {
-    // 0x3F8 is the stanadard first I/O port number for serial ports
-    let mut serial_port = unsafe { SerialPort::new(0x3F8) };
+    let mut serial_port = unsafe { SerialPort::new(COM1) };
     serial_port.init();
     Mutex::new(serial_port)
   };
 }
 
+/// Extends [SerialPort] with initialization on arbitrary
+/// ports and baud rates.
+pub trait SerialPortExt: Sized {
+  /// Create and initialize a serial port at I/O port `base`,
+  /// running at `baud` bits per second with 8-N-1 framing.
+  ///
+  /// Panics if `baud` is zero or can't be derived from the 115200
+  /// base rate by an integral divisor.
+  ///
+  /// # Safety
+  /// The caller must guarantee that `base` is the I/O port base of
+  /// a serial port, e.g., [COM1] or [COM2].
+  unsafe fn init_with(base: u16, baud: u32) -> Self;
+}
+
+impl SerialPortExt for SerialPort {
+  unsafe fn init_with(base: u16, baud: u32) -> Self {
+    assert!(
+      baud > 0 && baud <= UART_MAX_BAUD,
+      "unsupported baud rate {}",
+      baud
+    );
+    let divisor = UART_MAX_BAUD / baud;
+    assert_eq!(
+      divisor * baud,
+      UART_MAX_BAUD,
+      "baud rate {} doesn't divide {}",
+      baud,
+      UART_MAX_BAUD
+    );
+
+    let mut serial_port = unsafe { SerialPort::new(base) };
+    serial_port.init();
+
+    // Reprogram the divisor latch set up by `init`
+    let mut data: Port<u8> = Port::new(base);
+    let mut int_en: Port<u8> = Port::new(base + 1);
+    let mut line_ctrl: Port<u8> = Port::new(base + 3);
+    unsafe {
+      // Enable DLAB so that the first two registers address DLL/DLM
+      let line_config = line_ctrl.read();
+      line_ctrl.write(line_config | 0x80);
+      data.write(divisor as u8);
+      int_en.write((divisor >> 8) as u8);
+      line_ctrl.write(line_config & !0x80);
+    }
+
+    serial_port
+  }
+}
+
+/// Read a byte from the first serial port if one has been received,
+/// without blocking.
+pub fn try_read_byte() -> Option<u8> {
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    // hold the lock so that we don't race with writers on the same port
+    let _serial = SERIAL1.lock();
+    let mut line_sts: Port<u8> = Port::new(COM1 + 5);
+    let mut data: Port<u8> = Port::new(COM1);
+    unsafe {
+      // bit 0 of the line status register is set when data is ready
+      if line_sts.read() & 0x01 != 0 {
+        Some(data.read())
+      } else {
+        None
+      }
+    }
+  })
+}
+
+/// Read a byte from the first serial port, spinning until one has
+/// been received.
+pub fn read_byte() -> u8 {
+  loop {
+    if let Some(byte) = try_read_byte() {
+      return byte;
+    }
+    core::hint::spin_loop();
+  }
+}
+
+/// Print `len` bytes starting at `addr` to the serial port, formatted by
+/// [HexDump](crate::vga_buffer::HexDump).
+///
+/// # Safety
+/// The caller must guarantee that all `len` bytes from `addr` are mapped
+/// and readable, reading unmapped memory causes a page fault.
+pub unsafe fn hexdump(addr: *const u8, len: usize) {
+  let bytes = unsafe { core::slice::from_raw_parts(addr, len) };
+  crate::serial_print!("{}", crate::vga_buffer::HexDump(bytes));
+}
+
 #[doc(hidden)]
 pub fn _print(args: core::fmt::Arguments) {
   use core::fmt::Write;
   use x86_64::instructions::interrupts;
 
+  // Same as for the VGA buffer: an interrupt handler that prints while
+  // we hold the lock would deadlock, and the whole message is written
+  // under one lock so output from different contexts can't interleave.
   interrupts::without_interrupts(|| {
     SERIAL1.lock().write_fmt(args).unwrap();
   });
 }
 
+/// Print to the first serial port unless it's locked, in which case the
+/// output is dropped. Returns whether it was printed.
+///
+/// Like [try_print](crate::vga_buffer::try_print), this never blocks.
+pub fn try_serial_print(args: core::fmt::Arguments) -> bool {
+  use core::fmt::Write;
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| match SERIAL1.try_lock() {
+    Some(mut serial) => {
+      serial.write_fmt(args).unwrap();
+      true
+    }
+    None => false,
+  })
+}
+
 /// Print to the serial port
 #[macro_export]
 macro_rules! serial_print {
@@ -41,3 +164,20 @@ macro_rules! serial_println {
   ($fmt:expr, $($arg:tt)*) => ($crate::serial_print!(
     concat!($fmt, "\n"), $($arg)*));
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test_case]
+  fn test_try_serial_print_doesnt_block() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+      let serial = SERIAL1.lock();
+      assert!(!try_serial_print(format_args!("dropped")));
+      drop(serial);
+      assert!(try_serial_print(format_args!("")));
+    });
+  }
+}
@@ -0,0 +1,140 @@
+//! This module implements a bounded multi-producer, single-consumer
+//! channel for passing values between asynchronous tasks. It follows the
+//! same [ArrayQueue] + [AtomicWaker] scheme as the keyboard's
+//! [ScancodeStream](super::keyboard::ScancodeStream).
+
+use alloc::sync::Arc;
+use core::{
+  pin::Pin,
+  sync::atomic::{AtomicUsize, Ordering},
+  task::{Context, Poll},
+};
+use crossbeam_queue::ArrayQueue;
+use futures_util::{stream::Stream, task::AtomicWaker};
+
+/// State shared by all ends of a channel.
+struct Shared<T> {
+  queue: ArrayQueue<T>,
+  /// The waker of the task waiting on the [Receiver]
+  waker: AtomicWaker,
+  /// Number of live [Sender]s
+  senders: AtomicUsize,
+}
+
+/// Create a channel that buffers at most `capacity` values.
+///
+/// Panics if `capacity` is zero.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+  let shared = Arc::new(Shared {
+    queue: ArrayQueue::new(capacity),
+    waker: AtomicWaker::new(),
+    senders: AtomicUsize::new(1),
+  });
+  let sender = Sender {
+    shared: Arc::clone(&shared),
+  };
+  (sender, Receiver { shared })
+}
+
+/// The sending end of a channel, which can be cloned to
+/// send from multiple tasks.
+pub struct Sender<T> {
+  shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+  /// Send a value and wake up the receiver.
+  ///
+  /// Never blocks: if the channel is full, the value is handed back
+  /// as the error.
+  pub fn send(&self, value: T) -> Result<(), T> {
+    self.shared.queue.push(value).map_err(|err| err.0)?;
+    self.shared.waker.wake();
+    Ok(())
+  }
+}
+
+impl<T> Clone for Sender<T> {
+  fn clone(&self) -> Self {
+    self.shared.senders.fetch_add(1, Ordering::Relaxed);
+    Sender {
+      shared: Arc::clone(&self.shared),
+    }
+  }
+}
+
+impl<T> Drop for Sender<T> {
+  fn drop(&mut self) {
+    if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+      // let the receiver observe that the channel is closed
+      self.shared.waker.wake();
+    }
+  }
+}
+
+/// The receiving end of a channel. It yields the sent values in order,
+/// and ends once all [Sender]s are gone and the channel is drained.
+pub struct Receiver<T> {
+  shared: Arc<Shared<T>>,
+}
+
+impl<T> Stream for Receiver<T> {
+  type Item = T;
+
+  fn poll_next(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+  ) -> Poll<Option<Self::Item>> {
+    let shared = &self.shared;
+
+    // fast path
+    if let Ok(value) = shared.queue.pop() {
+      return Poll::Ready(Some(value));
+    }
+
+    shared.waker.register(cx.waker());
+
+    match shared.queue.pop() {
+      Ok(value) => {
+        shared.waker.take();
+        Poll::Ready(Some(value))
+      }
+      Err(_) if shared.senders.load(Ordering::Acquire) == 0 => {
+        // the last sender may have pushed right before leaving
+        Poll::Ready(shared.queue.pop().ok())
+      }
+      Err(_) => Poll::Pending,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use futures_util::{task::noop_waker_ref, StreamExt};
+
+  #[test_case]
+  fn test_channel_in_order() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let (sender, mut receiver) = channel(4);
+
+    for i in 0..3 {
+      sender.send(i).unwrap();
+    }
+    for i in 0..3 {
+      assert_eq!(receiver.poll_next_unpin(&mut ctx), Poll::Ready(Some(i)));
+    }
+    assert_eq!(receiver.poll_next_unpin(&mut ctx), Poll::Pending);
+
+    drop(sender);
+    assert_eq!(receiver.poll_next_unpin(&mut ctx), Poll::Ready(None));
+  }
+
+  #[test_case]
+  fn test_channel_full() {
+    let (sender, _receiver) = channel(1);
+
+    assert_eq!(sender.send(1), Ok(()));
+    assert_eq!(sender.send(2), Err(2));
+  }
+}
@@ -2,15 +2,45 @@
 //! support to manage asynchronous tasks more efficiently.
 
 use super::{Task, TaskId};
-use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
-use core::task::{Context, Waker};
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, task::Wake};
+use core::{
+  future::Future,
+  pin::Pin,
+  task::{Context, Poll, Waker},
+};
 use crossbeam_queue::ArrayQueue;
+use spin::{Mutex, RwLock};
+
+/// Initial capacity of the executor's task queue
+const INITIAL_QUEUE_CAPACITY: usize = 100;
+
+/// Scheduling priority of a task, see [Executor::spawn_with_priority].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Priority {
+  /// Polled before any ready [Priority::Low] task
+  High,
+  /// Only polled once no [Priority::High] task is ready
+  #[default]
+  Low,
+}
+
+impl Priority {
+  /// Index of the priority's band in [TaskQueue]
+  fn band(self) -> usize {
+    match self {
+      Priority::High => 0,
+      Priority::Low => 1,
+    }
+  }
+}
 
 /// The [Executor] type.
 pub struct Executor {
   tasks: BTreeMap<TaskId, Task>,
-  task_queue: Arc<ArrayQueue<TaskId>>,
+  task_queue: Arc<TaskQueue>,
   waker_cache: BTreeMap<TaskId, Waker>,
+  /// Callback invoked whenever the executor runs out of ready tasks
+  on_idle: Option<Box<dyn FnMut()>>,
 }
 
 impl Executor {
@@ -18,37 +48,125 @@ impl Executor {
   pub fn new() -> Self {
     Executor {
       tasks: BTreeMap::new(),
-      task_queue: Arc::new(ArrayQueue::new(100)),
+      task_queue: Arc::new(TaskQueue::new(INITIAL_QUEUE_CAPACITY)),
       waker_cache: BTreeMap::new(),
+      on_idle: None,
     }
   }
 
-  /// Spawn a new task.
-  pub fn spawn(&mut self, task: Task) {
+  /// Spawn a new task with the default [Priority::Low], returning its id.
+  pub fn spawn(&mut self, task: Task) -> TaskId {
+    self.spawn_with_priority(task, Priority::default())
+  }
+
+  /// Spawn a new task, returning its id. Whenever both are ready,
+  /// [Priority::High] tasks are polled before [Priority::Low] ones, so a
+  /// busy high priority task can starve the low priority ones.
+  pub fn spawn_with_priority(
+    &mut self,
+    task: Task,
+    priority: Priority,
+  ) -> TaskId {
     let task_id = task.task_id;
     if self.tasks.insert(task_id, task).is_some() {
       panic!("task with same ID already in tasks");
     }
-    self.task_queue.push(task_id).expect("queue full");
+    self.task_queue.push_or_grow(task_id, priority);
+    task_id
+  }
+
+  /// Abort the task with the given id, so that it's never polled again.
+  /// The task's future is dropped right away, and a [JoinHandle] of the
+  /// task never resolves.
+  ///
+  /// Aborting a task that already completed, or an unknown one, does nothing.
+  pub fn abort(&mut self, task_id: TaskId) {
+    self.tasks.remove(&task_id);
+    self.waker_cache.remove(&task_id);
+  }
+
+  /// Spawn a new task from a future with arbitrary output, returning
+  /// a [JoinHandle] that resolves to the output once the task completes.
+  pub fn spawn_with_handle<T: 'static>(
+    &mut self,
+    future: impl Future<Output = T> + 'static,
+  ) -> JoinHandle<T> {
+    let slot = Arc::new(Mutex::new(JoinSlot {
+      output: None,
+      waker: None,
+    }));
+
+    let task_slot = Arc::clone(&slot);
+    self.spawn(Task::new(async move {
+      let output = future.await;
+      let waker = {
+        let mut slot = task_slot.lock();
+        slot.output = Some(output);
+        slot.waker.take()
+      };
+      if let Some(waker) = waker {
+        waker.wake();
+      }
+    }));
+
+    JoinHandle { slot }
+  }
+
+  /// Returns the number of tasks that haven't completed yet.
+  pub fn task_count(&self) -> usize {
+    self.tasks.len()
+  }
+
+  /// Register a callback that is invoked by [Executor::run] whenever
+  /// all ready tasks have been polled and none is ready anymore, right
+  /// before the executor halts to wait for the next interrupt.
+  /// Replaces any previously registered callback.
+  pub fn on_idle(&mut self, f: impl FnMut() + 'static) {
+    self.on_idle = Some(Box::new(f));
   }
 
   /// Run the executor to completion.
   pub fn run(&mut self) -> ! {
     loop {
       self.run_ready_tasks();
+      self.notify_if_idle();
       self.sleep_if_idle();
     }
   }
 
-  /// This function scans the [task_queue] once and runs all the possibly ready tasks.
+  /// Poll tasks until none is ready anymore, then return instead of
+  /// halting like [Executor::run] does. Tasks that are still waiting to
+  /// be woken stay spawned, e.g., for a later call.
+  ///
+  /// The [Executor::on_idle] callback isn't invoked.
+  pub fn run_until_idle(&mut self) {
+    // a wake-up from an interrupt handler can arrive after the queue
+    // was drained, so check again before returning
+    while !self.task_queue.is_empty() {
+      self.run_ready_tasks();
+    }
+  }
+
+  /// Invoke the [Executor::on_idle] callback if no task is ready.
+  fn notify_if_idle(&mut self) {
+    if let Some(on_idle) = &mut self.on_idle {
+      if self.task_queue.is_empty() {
+        on_idle();
+      }
+    }
+  }
+
+  /// This function scans the [task_queue] once and runs all the possibly
+  /// ready tasks, high priority ones first.
   fn run_ready_tasks(&mut self) {
     let Self {
       tasks,
       task_queue,
       waker_cache,
+      ..
     } = self;
 
-    while let Ok(task_id) = task_queue.pop() {
+    while let Some((task_id, priority)) = task_queue.pop() {
       let task = match tasks.get_mut(&task_id) {
         Some(task) => task,
         // This happens if a wake-up happens before a task completes, so that
@@ -57,9 +175,9 @@ impl Executor {
         None => continue,
       };
 
-      let waker = waker_cache
-        .entry(task_id)
-        .or_insert_with(|| TaskWaker::new(task_id, Arc::clone(task_queue)));
+      let waker = waker_cache.entry(task_id).or_insert_with(|| {
+        TaskWaker::new(task_id, priority, Arc::clone(task_queue))
+      });
       let mut ctx = Context::from_waker(waker);
 
       match task.poll(&mut ctx) {
@@ -92,20 +210,120 @@ impl Default for Executor {
   }
 }
 
+/// The state shared between a task spawned by
+/// [Executor::spawn_with_handle] and its [JoinHandle].
+struct JoinSlot<T> {
+  /// Output of the task, once it completed
+  output: Option<T>,
+  /// Waker of the task awaiting the [JoinHandle]
+  waker: Option<Waker>,
+}
+
+/// A future that resolves to the output of a task spawned by
+/// [Executor::spawn_with_handle] once that task completes.
+pub struct JoinHandle<T> {
+  slot: Arc<Mutex<JoinSlot<T>>>,
+}
+
+impl<T> Future for JoinHandle<T> {
+  type Output = T;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+    let mut slot = self.slot.lock();
+    match slot.output.take() {
+      Some(output) => Poll::Ready(output),
+      None => {
+        slot.waker = Some(cx.waker().clone());
+        Poll::Pending
+      }
+    }
+  }
+}
+
+/// The queue of tasks ready to be polled, shared between the executor
+/// and its wakers. There is one band per [Priority].
+///
+/// Wakers may push from interrupt handlers, where we can't allocate,
+/// so only the executor grows the queue. It does so with interrupts
+/// disabled, so that no waker can observe the queue mid-migration.
+struct TaskQueue {
+  bands: [RwLock<ArrayQueue<TaskId>>; 2],
+}
+
+impl TaskQueue {
+  fn new(capacity: usize) -> Self {
+    TaskQueue {
+      bands: [
+        RwLock::new(ArrayQueue::new(capacity)),
+        RwLock::new(ArrayQueue::new(capacity)),
+      ],
+    }
+  }
+
+  /// Push a task id into the band of `priority`, handing it back if the
+  /// band is full.
+  fn push(&self, task_id: TaskId, priority: Priority) -> Result<(), TaskId> {
+    self.bands[priority.band()]
+      .read()
+      .push(task_id)
+      .map_err(|err| err.0)
+  }
+
+  /// Push a task id into the band of `priority`, moving all entries of
+  /// the band to a queue of twice the capacity if it's full.
+  fn push_or_grow(&self, task_id: TaskId, priority: Priority) {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+      let mut queue = self.bands[priority.band()].write();
+      if let Err(err) = queue.push(task_id) {
+        let grown = ArrayQueue::new(queue.capacity() * 2);
+        while let Ok(queued_id) = queue.pop() {
+          grown.push(queued_id).expect("grown queue is full");
+        }
+        grown.push(err.0).expect("grown queue is full");
+        *queue = grown;
+      }
+    });
+  }
+
+  /// Pop the next task id together with its priority, taking high
+  /// priority tasks first.
+  fn pop(&self) -> Option<(TaskId, Priority)> {
+    [Priority::High, Priority::Low]
+      .iter()
+      .find_map(|&priority| {
+        let task_id = self.bands[priority.band()].read().pop().ok()?;
+        Some((task_id, priority))
+      })
+  }
+
+  fn is_empty(&self) -> bool {
+    self.bands.iter().all(|band| band.read().is_empty())
+  }
+}
+
 /// The Waker type used by the executor.
 /// It wakes up by pushing the task_id to the task_queue so that
 /// it will be ready for polling in the next run. The task_queue
-/// is shared with the executor.
+/// is shared with the executor. The task goes back into the band of
+/// its priority.
 struct TaskWaker {
   task_id: TaskId,
-  task_queue: Arc<ArrayQueue<TaskId>>,
+  priority: Priority,
+  task_queue: Arc<TaskQueue>,
 }
 
 impl TaskWaker {
   #[allow(clippy::new_ret_no_self)]
-  fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
+  fn new(
+    task_id: TaskId,
+    priority: Priority,
+    task_queue: Arc<TaskQueue>,
+  ) -> Waker {
     Waker::from(Arc::new(TaskWaker {
       task_id,
+      priority,
       task_queue,
     }))
   }
@@ -113,7 +331,7 @@ impl TaskWaker {
   fn wake_task(&self) {
     self
       .task_queue
-      .push(self.task_id)
+      .push(self.task_id, self.priority)
       .expect("Task Queue is full");
   }
 }
@@ -127,3 +345,123 @@ impl Wake for TaskWaker {
     self.wake_task();
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test_case]
+  fn test_join_handle() {
+    let mut executor = Executor::new();
+    let result = Arc::new(Mutex::new(None));
+
+    let handle = executor.spawn_with_handle(async { 42 });
+    let joined = Arc::clone(&result);
+    executor.spawn(Task::new(async move {
+      *joined.lock() = Some(handle.await);
+    }));
+    executor.run_ready_tasks();
+
+    assert_eq!(*result.lock(), Some(42));
+  }
+
+  #[test_case]
+  fn test_spawn_many() {
+    let mut executor = Executor::new();
+    for _ in 0..2 * INITIAL_QUEUE_CAPACITY {
+      executor.spawn(Task::new(async {}));
+    }
+    executor.run_ready_tasks();
+
+    assert!(executor.tasks.is_empty());
+  }
+
+  #[test_case]
+  fn test_abort() {
+    let mut executor = Executor::new();
+    let task_id = executor.spawn(Task::new(core::future::pending()));
+    executor.run_ready_tasks();
+    assert!(executor.tasks.contains_key(&task_id));
+
+    executor.abort(task_id);
+    assert!(executor.tasks.is_empty());
+    assert!(executor.waker_cache.is_empty());
+
+    // aborting twice is fine
+    executor.abort(task_id);
+  }
+
+  #[test_case]
+  fn test_run_until_idle() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    let mut executor = Executor::new();
+    let count = Arc::new(AtomicUsize::new(0));
+    for _ in 0..3 {
+      let counter = Arc::clone(&count);
+      executor.spawn(Task::new(async move {
+        for _ in 0..2 {
+          counter.fetch_add(1, Ordering::Relaxed);
+          crate::task::yield_now().await;
+        }
+      }));
+    }
+    executor.spawn(Task::new(core::future::pending()));
+
+    executor.run_until_idle();
+    assert_eq!(count.load(Ordering::Relaxed), 6);
+    assert_eq!(executor.task_count(), 1);
+  }
+
+  #[test_case]
+  fn test_high_priority_first() {
+    use alloc::vec::Vec;
+
+    let mut executor = Executor::new();
+    let order = Arc::new(Mutex::new(Vec::new()));
+    for (name, priority) in [
+      ("low", Priority::Low),
+      ("high", Priority::High),
+      ("low", Priority::Low),
+      ("high", Priority::High),
+    ] {
+      let order = Arc::clone(&order);
+      executor.spawn_with_priority(
+        Task::new(async move {
+          order.lock().push(name);
+          // the wake-up keeps the task in its band
+          crate::task::yield_now().await;
+          order.lock().push(name);
+        }),
+        priority,
+      );
+    }
+
+    executor.run_until_idle();
+    assert_eq!(
+      *order.lock(),
+      ["high", "high", "high", "high", "low", "low", "low", "low"]
+    );
+  }
+
+  #[test_case]
+  fn test_task_count_and_on_idle() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    let mut executor = Executor::new();
+    let idle_count = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&idle_count);
+    executor.on_idle(move || {
+      counter.fetch_add(1, Ordering::Relaxed);
+    });
+
+    executor.spawn(Task::new(async {}));
+    executor.spawn(Task::new(core::future::pending()));
+    assert_eq!(executor.task_count(), 2);
+
+    executor.run_ready_tasks();
+    executor.notify_if_idle();
+    assert_eq!(executor.task_count(), 1);
+    assert_eq!(idle_count.load(Ordering::Relaxed), 1);
+  }
+}
@@ -5,6 +5,9 @@
 
 use crate::print;
 use crate::println;
+use crate::vga_buffer;
+use crate::warn;
+use alloc::{boxed::Box, string::String};
 use conquer_once::spin::OnceCell;
 use core::{
   pin::Pin,
@@ -13,7 +16,10 @@ use core::{
 use crossbeam_queue::ArrayQueue;
 use futures_util::task::AtomicWaker;
 use futures_util::{stream::Stream, StreamExt};
-use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
+use pc_keyboard::{
+  layouts, DecodedKey, HandleControl, KeyCode, KeyEvent, KeyState, Keyboard,
+  KeyboardLayout, ScancodeSet, ScancodeSet1, ScancodeSet2,
+};
 
 static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
 
@@ -26,13 +32,100 @@ static WAKER: AtomicWaker = AtomicWaker::new();
 pub(crate) fn add_scancode(scancode: u8) {
   if let Ok(queue) = SCANCODE_QUEUE.try_get() {
     if queue.push(scancode).is_err() {
-      println!("WARNING: scancode queue full; dropping keyboard input");
+      warn!("scancode queue full; dropping keyboard input");
     } else {
       // wake up whatever task that's waiting on us
       WAKER.wake();
     }
   } else {
-    println!("WARNING: scancode queue uninitialized");
+    warn!("scancode queue uninitialized");
+  }
+}
+
+/// State of the modifier keys, as tracked by the keyboard task.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ModifierState {
+  /// Either Shift key is held
+  pub shift: bool,
+  /// Either Ctrl key is held
+  pub ctrl: bool,
+  /// Either Alt key is held
+  pub alt: bool,
+  /// Caps Lock is on
+  pub caps_lock: bool,
+  /// Num Lock is on
+  pub num_lock: bool,
+  /// Scroll Lock is on
+  pub scroll_lock: bool,
+  /// Whether the left and the right Shift key are held
+  shift_keys: [bool; 2],
+  /// Whether the left and the right Ctrl key are held
+  ctrl_keys: [bool; 2],
+  /// Whether the left and the right Alt key are held
+  alt_keys: [bool; 2],
+}
+
+impl ModifierState {
+  /// Update the state with a key press or release.
+  pub fn update(&mut self, event: &KeyEvent) {
+    let down = event.state == KeyState::Down;
+    match event.code {
+      KeyCode::ShiftLeft => self.shift_keys[0] = down,
+      KeyCode::ShiftRight => self.shift_keys[1] = down,
+      KeyCode::ControlLeft => self.ctrl_keys[0] = down,
+      KeyCode::ControlRight => self.ctrl_keys[1] = down,
+      KeyCode::AltLeft => self.alt_keys[0] = down,
+      KeyCode::AltRight => self.alt_keys[1] = down,
+      // locks toggle on every press
+      KeyCode::CapsLock if down => self.caps_lock = !self.caps_lock,
+      KeyCode::NumpadLock if down => self.num_lock = !self.num_lock,
+      KeyCode::ScrollLock if down => self.scroll_lock = !self.scroll_lock,
+      _ => {}
+    }
+    // a modifier stays held until both of its keys are released
+    self.shift = self.shift_keys[0] || self.shift_keys[1];
+    self.ctrl = self.ctrl_keys[0] || self.ctrl_keys[1];
+    self.alt = self.alt_keys[0] || self.alt_keys[1];
+  }
+
+  fn locks(&self) -> (bool, bool, bool) {
+    (self.caps_lock, self.num_lock, self.scroll_lock)
+  }
+}
+
+/// Modifier state shared with other tasks, see [modifiers].
+static MODIFIERS: spin::Mutex<ModifierState> =
+  spin::Mutex::new(ModifierState {
+    shift: false,
+    ctrl: false,
+    alt: false,
+    caps_lock: false,
+    num_lock: false,
+    scroll_lock: false,
+    shift_keys: [false; 2],
+    ctrl_keys: [false; 2],
+    alt_keys: [false; 2],
+  });
+
+/// Returns the current state of the modifier keys, e.g. to check whether
+/// Ctrl is held.
+pub fn modifiers() -> ModifierState {
+  *MODIFIERS.lock()
+}
+
+/// Update the shared modifier state with a key event, and the keyboard
+/// LEDs if a lock key was toggled.
+fn track_modifiers(event: &KeyEvent) {
+  let (before, after) = {
+    let mut modifiers = MODIFIERS.lock();
+    let before = modifiers.locks();
+    modifiers.update(event);
+    (before, modifiers.locks())
+  };
+
+  if before != after {
+    let (caps, num, scroll) = after;
+    crate::interrupts::set_keyboard_leds(caps, num, scroll);
   }
 }
 
@@ -83,20 +176,255 @@ impl Stream for ScancodeStream {
   }
 }
 
+/// Decodes scancodes into keys, implemented for every [Keyboard] so that
+/// [read_line_with] can keep one around whatever its layout.
+trait KeyDecoder: Send {
+  /// Feed a scancode to the keyboard, returning the key if it completed
+  /// one. Key presses and releases are tracked in the shared
+  /// [ModifierState].
+  fn decode(&mut self, code: u8) -> Option<DecodedKey>;
+}
+
+impl<L, S> KeyDecoder for Keyboard<L, S>
+where
+  L: KeyboardLayout + Send,
+  S: ScancodeSet + Send,
+{
+  fn decode(&mut self, code: u8) -> Option<DecodedKey> {
+    match self.add_byte(code) {
+      Ok(Some(event)) => {
+        track_modifiers(&event);
+        self.process_keyevent(event)
+      }
+      _ => None,
+    }
+  }
+}
+
+/// Keyboard state machine shared by [read_line_with] calls, so that keys
+/// held across lines are tracked, along with the configuration it decodes
+/// with.
+static LINE_KEYBOARD: spin::Mutex<
+  Option<(KeyboardConfig, Box<dyn KeyDecoder>)>,
+> = spin::Mutex::new(None);
+
+/// Create a keyboard decoding keys as given by `config`, see
+/// [print_keypress_with].
+fn line_keyboard(config: KeyboardConfig) -> Box<dyn KeyDecoder> {
+  match config.layout {
+    Layout::Us104 => line_keyboard_in(layouts::Us104Key, config),
+    Layout::Uk105 => line_keyboard_in(layouts::Uk105Key, config),
+    Layout::Dvorak104 => line_keyboard_in(layouts::Dvorak104Key, config),
+    Layout::Azerty => line_keyboard_in(layouts::Azerty, config),
+    Layout::Jis109 => line_keyboard_in(layouts::Jis109Key, config),
+  }
+}
+
+fn line_keyboard_in<L: KeyboardLayout + Send + 'static>(
+  layout: L,
+  config: KeyboardConfig,
+) -> Box<dyn KeyDecoder> {
+  let handle_control = config.handle_control;
+  match config.scancode_set {
+    ScancodeSetKind::Set1 => {
+      Box::new(Keyboard::new(layout, ScancodeSet1, handle_control))
+    }
+    ScancodeSetKind::Set2 => {
+      Box::new(Keyboard::new(layout, ScancodeSet2, handle_control))
+    }
+  }
+}
+
+/// Read a line from the keyboard, echoing it to the screen, using a US
+/// keyboard layout. See [read_line_with].
+pub async fn read_line() -> String {
+  read_line_with(KeyboardConfig::default()).await
+}
+
+/// Read a line from the keyboard, decoding keys as given by `config`, and
+/// echo it to the screen. Backspace erases the last character, PageUp and
+/// PageDown scroll through the screen's history, and other keys that don't
+/// produce a printable character are ignored. The line is returned without
+/// the newline when Enter is pressed.
+///
+/// This consumes the scancode queue, so it shouldn't run concurrently with
+/// [print_keypress] or another `read_line_with`.
+pub async fn read_line_with(config: KeyboardConfig) -> String {
+  // the queue might already be set up by an earlier call
+  let _ = SCANCODE_QUEUE.try_init_once(|| ArrayQueue::new(100));
+  let mut scancode_stream = ScancodeStream { _private: () };
+  let mut line = String::new();
+
+  {
+    // keep the keyboard of earlier calls unless the configuration changed
+    let mut keyboard = LINE_KEYBOARD.lock();
+    if !matches!(&*keyboard, Some((current, _)) if *current == config) {
+      *keyboard = Some((config, line_keyboard(config)));
+    }
+  }
+
+  while let Some(code) = scancode_stream.next().await {
+    let key = match &mut *LINE_KEYBOARD.lock() {
+      Some((_, keyboard)) => keyboard.decode(code),
+      None => None,
+    };
+    if let Some(key) = key {
+      if edit_line(&mut line, key) {
+        break;
+      }
+    }
+  }
+  line
+}
+
+/// Apply a decoded key to the line being read, echoing the change to the
+/// screen. Returns true once the line is complete.
+fn edit_line(line: &mut String, key: DecodedKey) -> bool {
+  if scroll_history(key) {
+    return false;
+  }
+  match key {
+    DecodedKey::Unicode('\n') => {
+      println!();
+      return true;
+    }
+    DecodedKey::Unicode('\x08') => {
+      // erase the character from the screen as well
+      if line.pop().is_some() {
+        print!("\x08");
+      }
+    }
+    DecodedKey::Unicode(character) if !character.is_control() => {
+      line.push(character);
+      print!("{}", character);
+    }
+    _ => {}
+  }
+  false
+}
+
+/// Number of rows scrolled by PageUp and PageDown
+const PAGE_SCROLL_ROWS: usize = vga_buffer::BUFFER_HEIGHT / 2;
+
+/// Scroll the screen's history if `key` is PageUp or PageDown, see
+/// [vga_buffer::scroll_up]. Any other key returns to the live output,
+/// where it's echoed. Returns whether the key was used for scrolling.
+fn scroll_history(key: DecodedKey) -> bool {
+  match key {
+    DecodedKey::RawKey(KeyCode::PageUp) => {
+      vga_buffer::scroll_up(PAGE_SCROLL_ROWS);
+      true
+    }
+    DecodedKey::RawKey(KeyCode::PageDown) => {
+      vga_buffer::scroll_down(PAGE_SCROLL_ROWS);
+      true
+    }
+    _ => {
+      vga_buffer::scroll_to_bottom();
+      false
+    }
+  }
+}
+
+/// Keyboard layouts supported by [print_keypress_with] and
+/// [read_line_with].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+  /// US 104-key
+  Us104,
+  /// UK 105-key
+  Uk105,
+  /// US 104-key with Dvorak mapping
+  Dvorak104,
+  /// French AZERTY
+  Azerty,
+  /// Japanese 109-key
+  Jis109,
+}
+
+/// Scancode sets supported by [print_keypress_with] and [read_line_with].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScancodeSetKind {
+  /// Scancode set 1, which the PS/2 controller translates to by default
+  Set1,
+  /// Scancode set 2
+  Set2,
+}
+
+/// Configuration of the keyboard decoding in [print_keypress_with] and
+/// [read_line_with].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyboardConfig {
+  /// Layout mapping keys to characters
+  pub layout: Layout,
+  /// Scancode set sent by the keyboard
+  pub scancode_set: ScancodeSetKind,
+  /// How keys pressed along with Ctrl are decoded
+  pub handle_control: HandleControl,
+}
+
+impl Default for KeyboardConfig {
+  fn default() -> Self {
+    KeyboardConfig {
+      layout: Layout::Us104,
+      scancode_set: ScancodeSetKind::Set1,
+      handle_control: HandleControl::Ignore,
+    }
+  }
+}
+
 /// This creates a long-running task that monitors the [SCANCODE_QUEUE]
-/// and prints to the screen if a valid character is available.
+/// and prints to the screen if a valid character is available, using a
+/// US keyboard layout.
 ///
 /// This function should be called only once as it initializes the global stream
 /// inside.
 pub async fn print_keypress() {
+  print_keypress_with(KeyboardConfig::default()).await
+}
+
+/// Like [print_keypress], but decoding keys as given by `config`.
+pub async fn print_keypress_with(config: KeyboardConfig) {
+  // the layout and scancode set are type parameters of the keyboard,
+  // so each combination is a separate instantiation
+  match config.layout {
+    Layout::Us104 => print_keypress_in(layouts::Us104Key, config).await,
+    Layout::Uk105 => print_keypress_in(layouts::Uk105Key, config).await,
+    Layout::Dvorak104 => print_keypress_in(layouts::Dvorak104Key, config).await,
+    Layout::Azerty => print_keypress_in(layouts::Azerty, config).await,
+    Layout::Jis109 => print_keypress_in(layouts::Jis109Key, config).await,
+  }
+}
+
+async fn print_keypress_in<L: KeyboardLayout>(
+  layout: L,
+  config: KeyboardConfig,
+) {
+  let handle_control = config.handle_control;
+  match config.scancode_set {
+    ScancodeSetKind::Set1 => {
+      print_keys(Keyboard::new(layout, ScancodeSet1, handle_control)).await
+    }
+    ScancodeSetKind::Set2 => {
+      print_keys(Keyboard::new(layout, ScancodeSet2, handle_control)).await
+    }
+  }
+}
+
+/// Print the keys decoded by the `keyboard` state machine.
+async fn print_keys<L: KeyboardLayout, S: ScancodeSet>(
+  mut keyboard: Keyboard<L, S>,
+) {
   let mut scancode_stream = ScancodeStream::new();
-  // keyboard state machine to handle keyboard events
-  let mut keyboard =
-    Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore);
 
   while let Some(code) = scancode_stream.next().await {
     if let Ok(Some(event)) = keyboard.add_byte(code) {
+      // track presses and releases before the event is consumed
+      track_modifiers(&event);
       if let Some(key) = keyboard.process_keyevent(event) {
+        if scroll_history(key) {
+          continue;
+        }
         match key {
           DecodedKey::Unicode(character) => print!("{}", character),
           DecodedKey::RawKey(key) => print!("{:?}", key),
@@ -105,3 +433,61 @@ pub async fn print_keypress() {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test_case]
+  fn test_modifier_state() {
+    let mut state = ModifierState::default();
+    state.update(&KeyEvent::new(KeyCode::ControlLeft, KeyState::Down));
+    state.update(&KeyEvent::new(KeyCode::ShiftRight, KeyState::Down));
+    assert!(state.ctrl && state.shift);
+
+    state.update(&KeyEvent::new(KeyCode::ControlLeft, KeyState::Up));
+    assert!(!state.ctrl && state.shift);
+
+    // releasing one Shift key while the other is held keeps shift on
+    state.update(&KeyEvent::new(KeyCode::ShiftLeft, KeyState::Down));
+    state.update(&KeyEvent::new(KeyCode::ShiftRight, KeyState::Up));
+    assert!(state.shift);
+    state.update(&KeyEvent::new(KeyCode::ShiftLeft, KeyState::Up));
+    assert!(!state.shift);
+
+    state.update(&KeyEvent::new(KeyCode::CapsLock, KeyState::Down));
+    state.update(&KeyEvent::new(KeyCode::CapsLock, KeyState::Up));
+    assert!(state.caps_lock);
+    state.update(&KeyEvent::new(KeyCode::CapsLock, KeyState::Down));
+    assert!(!state.caps_lock);
+  }
+
+  #[test_case]
+  fn test_line_keyboard_layout() {
+    // set 1 scancode of the key labelled Q on a US keyboard
+    const Q_PRESSED: u8 = 0x10;
+    let mut keyboard = line_keyboard(KeyboardConfig::default());
+    assert_eq!(keyboard.decode(Q_PRESSED), Some(DecodedKey::Unicode('q')));
+
+    let mut keyboard = line_keyboard(KeyboardConfig {
+      layout: Layout::Azerty,
+      ..KeyboardConfig::default()
+    });
+    assert_eq!(keyboard.decode(Q_PRESSED), Some(DecodedKey::Unicode('a')));
+  }
+
+  #[test_case]
+  fn test_edit_line() {
+    let mut line = String::new();
+    // backspace on an empty line does nothing
+    assert!(!edit_line(&mut line, DecodedKey::Unicode('\x08')));
+    for c in "lsx".chars() {
+      assert!(!edit_line(&mut line, DecodedKey::Unicode(c)));
+    }
+    assert!(!edit_line(&mut line, DecodedKey::Unicode('\x08')));
+    assert!(!edit_line(&mut line, DecodedKey::RawKey(KeyCode::ArrowUp)));
+    assert!(!edit_line(&mut line, DecodedKey::Unicode('\t')));
+    assert!(edit_line(&mut line, DecodedKey::Unicode('\n')));
+    assert_eq!(line, "ls");
+  }
+}
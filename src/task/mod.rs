@@ -1,9 +1,12 @@
 //! This module contains the kernels multi-tasking functionalities,
 //! which includes an executor to run asynchronous tasks in the kernel.
 
+pub mod channel;
 pub mod executor;
 pub mod keyboard;
+pub mod shell;
 pub mod simple_executor;
+pub mod timer;
 
 use alloc::boxed::Box;
 use core::{
@@ -15,7 +18,7 @@ use core::{
 
 /// Unique identifier of tasks.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-struct TaskId(u64);
+pub struct TaskId(u64);
 
 impl TaskId {
   /// Generate an auto-incrementing task id.
@@ -47,3 +50,145 @@ impl Task {
     self.inner_future.as_mut().poll(ctx)
   }
 }
+
+/// Returns a future that yields control back to the executor once, so
+/// that other ready tasks get polled before the current task continues.
+pub fn yield_now() -> YieldNow {
+  YieldNow { yielded: false }
+}
+
+/// The future returned by [yield_now].
+pub struct YieldNow {
+  yielded: bool,
+}
+
+impl Future for YieldNow {
+  type Output = ();
+
+  fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+    if self.yielded {
+      return Poll::Ready(());
+    }
+
+    self.yielded = true;
+    // reschedule ourselves right away, we're not waiting on anything
+    cx.waker().wake_by_ref();
+    Poll::Pending
+  }
+}
+
+/// The output of [select]: which of the two futures completed first,
+/// with its output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Either<A, B> {
+  /// The first future completed first
+  Left(A),
+  /// The second future completed first
+  Right(B),
+}
+
+/// Returns a future that polls both `a` and `b`, and resolves to the
+/// output of whichever completes first. The other one is dropped right
+/// away, without being polled again.
+///
+/// If both are ready on the same poll, `a` wins. E.g., wait for a
+/// keypress with a timeout by selecting between the next scancode and
+/// a [timer::sleep].
+pub fn select<A: Future, B: Future>(a: A, b: B) -> Select<A, B> {
+  Select {
+    a: Some(a),
+    b: Some(b),
+  }
+}
+
+/// The future returned by [select].
+pub struct Select<A, B> {
+  /// The futures still running, both `None` once one completed
+  a: Option<A>,
+  b: Option<B>,
+}
+
+impl<A: Future, B: Future> Future for Select<A, B> {
+  type Output = Either<A::Output, B::Output>;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    // Safety: `a` and `b` are pinned along with `self`. They're never
+    // moved out, only dropped in place through `Pin::set`.
+    let this = unsafe { self.get_unchecked_mut() };
+    let mut a = unsafe { Pin::new_unchecked(&mut this.a) };
+    let mut b = unsafe { Pin::new_unchecked(&mut this.b) };
+    assert!(a.is_some(), "select polled after completion");
+
+    // both children are polled with our context, so either one waking
+    // up wakes the task awaiting the select
+    let a_poll = a.as_mut().as_pin_mut().map(|a| a.poll(cx));
+    if let Some(Poll::Ready(output)) = a_poll {
+      a.set(None);
+      b.set(None);
+      return Poll::Ready(Either::Left(output));
+    }
+    let b_poll = b.as_mut().as_pin_mut().map(|b| b.poll(cx));
+    if let Some(Poll::Ready(output)) = b_poll {
+      a.set(None);
+      b.set(None);
+      return Poll::Ready(Either::Right(output));
+    }
+    Poll::Pending
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use futures_util::task::noop_waker_ref;
+
+  #[test_case]
+  fn test_yield_now() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut future = yield_now();
+
+    assert_eq!(Pin::new(&mut future).poll(&mut ctx), Poll::Pending);
+    assert_eq!(Pin::new(&mut future).poll(&mut ctx), Poll::Ready(()));
+  }
+
+  #[test_case]
+  fn test_select_ready() {
+    use core::future::{pending, ready};
+
+    let mut ctx = Context::from_waker(noop_waker_ref());
+
+    let mut left = select(ready(1), pending::<()>());
+    assert_eq!(
+      Pin::new(&mut left).poll(&mut ctx),
+      Poll::Ready(Either::Left(1))
+    );
+
+    let mut right = select(pending::<()>(), ready('b'));
+    assert_eq!(
+      Pin::new(&mut right).poll(&mut ctx),
+      Poll::Ready(Either::Right('b'))
+    );
+  }
+
+  #[test_case]
+  fn test_select_drops_loser() {
+    use alloc::sync::Arc;
+
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let resource = Arc::new(());
+    let held = Arc::clone(&resource);
+    let loser = async move {
+      let _held = held;
+      core::future::pending::<()>().await
+    };
+
+    let mut future = core::pin::pin!(select(loser, yield_now()));
+    assert_eq!(future.as_mut().poll(&mut ctx), Poll::Pending);
+    assert_eq!(Arc::strong_count(&resource), 2);
+    assert_eq!(
+      future.as_mut().poll(&mut ctx),
+      Poll::Ready(Either::Right(()))
+    );
+    assert_eq!(Arc::strong_count(&resource), 1);
+  }
+}
@@ -0,0 +1,188 @@
+//! A simple interactive shell. Lines typed on the keyboard are split on
+//! whitespace, and the first word selects a command that is called with
+//! the rest of the words as arguments.
+//!
+//! The built-in commands are `help`, `mem`, `ticks` and `clear`, more can
+//! be added with [register].
+
+use super::channel::{channel, Receiver, Sender};
+use super::executor::{Executor, Priority};
+use super::keyboard::{read_line_with, KeyboardConfig};
+use super::Task;
+use crate::{print, println};
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use futures_util::StreamExt;
+use lazy_static::lazy_static;
+
+/// A shell command, called with the words following its name.
+pub type Command = fn(&[&str]);
+
+/// Number of lines buffered between the keyboard and the shell
+const LINE_QUEUE_CAPACITY: usize = 16;
+
+/// The prompt printed when the shell waits for a line
+const PROMPT: &str = "> ";
+
+lazy_static! {
+  /// Registered commands by name
+  static ref COMMANDS: spin::Mutex<BTreeMap<&'static str, Command>> = {
+    let mut commands = BTreeMap::new();
+    commands.insert("help", help as Command);
+    commands.insert("mem", mem as Command);
+    commands.insert("ticks", ticks as Command);
+    commands.insert("clear", clear as Command);
+    spin::Mutex::new(commands)
+  };
+}
+
+/// Register `command` under `name`, replacing any command registered
+/// under the same name before, including the built-in ones.
+pub fn register(name: &'static str, command: Command) {
+  COMMANDS.lock().insert(name, command);
+}
+
+/// Run the command named by the first word of `line` with the remaining
+/// words as arguments. Returns false if there is no such command. A blank
+/// line does nothing.
+pub fn dispatch(line: &str) -> bool {
+  let words: Vec<&str> = line.split_whitespace().collect();
+  let (name, args) = match words.split_first() {
+    Some(split) => split,
+    None => return true,
+  };
+  // release the lock first, so commands can look at or register commands
+  let command = COMMANDS.lock().get(name).copied();
+  match command {
+    Some(command) => {
+      command(args);
+      true
+    }
+    None => false,
+  }
+}
+
+/// Dispatch every line received on `lines`, printing a prompt before
+/// waiting for each one. Returns once the channel is closed.
+pub async fn run(mut lines: Receiver<String>) {
+  print!("{}", PROMPT);
+  while let Some(line) = lines.next().await {
+    if !dispatch(&line) {
+      println!("unknown command: {}, try help", line.trim());
+    }
+    print!("{}", PROMPT);
+  }
+}
+
+/// Read lines from the keyboard with a US keyboard layout and send them to
+/// the shell. See [read_lines_with].
+pub async fn read_lines(lines: Sender<String>) {
+  read_lines_with(lines, KeyboardConfig::default()).await
+}
+
+/// Read lines from the keyboard, decoding keys as given by `config`, and
+/// send them to the shell.
+///
+/// Like [read_line_with], this consumes the scancode queue, so it shouldn't
+/// run together with [print_keypress](super::keyboard::print_keypress).
+pub async fn read_lines_with(lines: Sender<String>, config: KeyboardConfig) {
+  loop {
+    let line = read_line_with(config).await;
+    if lines.send(line).is_err() {
+      println!("shell is busy, line dropped");
+    }
+  }
+}
+
+/// Spawn a shell reading from the keyboard with a US keyboard layout on
+/// `executor`. See [spawn_with].
+pub fn spawn(executor: &mut Executor) {
+  spawn_with(executor, KeyboardConfig::default());
+}
+
+/// Spawn a shell reading from the keyboard on `executor`, decoding keys as
+/// given by `config`. Keyboard input is handled at [Priority::High], so
+/// typing stays responsive.
+pub fn spawn_with(executor: &mut Executor, config: KeyboardConfig) {
+  let (sender, receiver) = channel(LINE_QUEUE_CAPACITY);
+  executor.spawn_with_priority(
+    Task::new(read_lines_with(sender, config)),
+    Priority::High,
+  );
+  executor.spawn(Task::new(run(receiver)));
+}
+
+/// List the registered commands.
+fn help(_args: &[&str]) {
+  println!("commands:");
+  for name in COMMANDS.lock().keys() {
+    println!("  {}", name);
+  }
+}
+
+/// Print heap usage statistics.
+fn mem(_args: &[&str]) {
+  #[cfg(not(feature = "fixed"))]
+  println!("heap: {}", crate::allocator::stats());
+  #[cfg(feature = "fixed")]
+  println!("heap statistics aren't available with the fixed size allocator");
+}
+
+/// Print the number of timer ticks and the uptime.
+fn ticks(_args: &[&str]) {
+  let ticks = crate::interrupts::ticks();
+  println!(
+    "{} ticks, {} ms",
+    ticks,
+    crate::interrupts::ticks_to_ms(ticks)
+  );
+}
+
+/// Clear the screen.
+fn clear(_args: &[&str]) {
+  crate::vga_buffer::clear_screen();
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::{borrow::ToOwned, string::ToString};
+
+  lazy_static! {
+    /// Arguments the `record` test command was called with
+    static ref RECORDED: spin::Mutex<Vec<String>> = spin::Mutex::new(Vec::new());
+  }
+
+  fn record(args: &[&str]) {
+    let mut recorded = RECORDED.lock();
+    recorded.clear();
+    recorded.extend(args.iter().map(|arg| arg.to_string()));
+  }
+
+  #[test_case]
+  fn test_dispatch() {
+    assert!(dispatch("mem\n"));
+    assert!(dispatch("  ticks  "));
+    assert!(dispatch(""));
+    assert!(!dispatch("no-such-command\n"));
+
+    register("record", record);
+    assert!(dispatch("record  a b\n"));
+    assert_eq!(*RECORDED.lock(), ["a", "b"]);
+  }
+
+  #[test_case]
+  fn test_run_dispatches_lines() {
+    register("record", record);
+    let (sender, receiver) = channel(LINE_QUEUE_CAPACITY);
+    sender.send("record from channel\n".to_owned()).unwrap();
+    sender.send("mem\n".to_owned()).unwrap();
+    drop(sender);
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(run(receiver)));
+    executor.run_until_idle();
+
+    assert_eq!(*RECORDED.lock(), ["from", "channel"]);
+    assert_eq!(executor.task_count(), 0);
+  }
+}
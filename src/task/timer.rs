@@ -0,0 +1,181 @@
+//! This module provides futures that complete after a number of
+//! timer ticks. Sleeping tasks register their wakers in a fixed-size
+//! table which the timer interrupt handler scans on every tick.
+
+use crate::interrupts::ticks;
+use core::{
+  future::Future,
+  pin::Pin,
+  task::{Context, Poll, Waker},
+};
+use spin::Mutex;
+
+/// Maximum number of tasks that can be sleeping at the same time.
+/// Further sleepers fall back to polling on every executor run.
+const MAX_SLEEPERS: usize = 64;
+
+/// A registered sleeper: the tick it waits for and the waker to fire.
+struct Sleeper {
+  deadline: u64,
+  waker: Waker,
+}
+
+/// Sleepers waiting for the timer. Locked with interrupts disabled on
+/// the task side, the interrupt handler only ever tries the lock.
+static SLEEPERS: Mutex<[Option<Sleeper>; MAX_SLEEPERS]> = {
+  const EMPTY: Option<Sleeper> = None;
+  Mutex::new([EMPTY; MAX_SLEEPERS])
+};
+
+/// Called by the timer interrupt handler with the current tick count.
+///
+/// Must not block or allocate: wakers are only woken by reference, and
+/// dropping them is left to the [Sleep] future.
+pub(crate) fn wake_sleepers(now: u64) {
+  // if a task is registering right now, we'll catch up on the next tick
+  if let Some(mut sleepers) = SLEEPERS.try_lock() {
+    for sleeper in sleepers.iter_mut().flatten() {
+      if sleeper.deadline <= now {
+        sleeper.waker.wake_by_ref();
+        // disarm, so that we wake the task only once
+        sleeper.deadline = u64::MAX;
+      }
+    }
+  }
+}
+
+/// Returns a future that completes once the timer advanced by `ticks`
+/// ticks, see [crate::interrupts::ticks].
+pub fn sleep(ticks: u64) -> Sleep {
+  Sleep {
+    deadline: self::ticks() + ticks,
+    slot: None,
+  }
+}
+
+/// The future returned by [sleep].
+pub struct Sleep {
+  /// Tick count at which the future completes
+  deadline: u64,
+  /// Index of our entry in [SLEEPERS], if registered
+  slot: Option<usize>,
+}
+
+impl Sleep {
+  /// Register or update our waker in [SLEEPERS]. Returns false if the
+  /// table is full.
+  fn register(&mut self, waker: &Waker) -> bool {
+    use x86_64::instructions::interrupts;
+
+    let (registered, replaced) = interrupts::without_interrupts(|| {
+      let mut sleepers = SLEEPERS.lock();
+      let slot = match self.slot {
+        Some(slot) => slot,
+        None => match sleepers.iter().position(Option::is_none) {
+          Some(slot) => slot,
+          None => return (false, None),
+        },
+      };
+      self.slot = Some(slot);
+
+      if let Some(sleeper) = &sleepers[slot] {
+        // still armed with a waker for the same task, don't clone it again
+        if sleeper.deadline == self.deadline && sleeper.waker.will_wake(waker) {
+          return (true, None);
+        }
+      }
+      let sleeper = Sleeper {
+        deadline: self.deadline,
+        waker: waker.clone(),
+      };
+      (true, sleepers[slot].replace(sleeper))
+    });
+    // like in unregister, drop the old waker only after releasing the lock
+    drop(replaced);
+    registered
+  }
+
+  /// Remove our entry from [SLEEPERS], if any.
+  fn unregister(&mut self) {
+    use x86_64::instructions::interrupts;
+
+    if let Some(slot) = self.slot.take() {
+      let sleeper =
+        interrupts::without_interrupts(|| SLEEPERS.lock()[slot].take());
+      // drop the waker only after releasing the lock and re-enabling
+      // interrupts, as that might free the memory backing it
+      drop(sleeper);
+    }
+  }
+}
+
+impl Future for Sleep {
+  type Output = ();
+
+  fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+    // the deadline may already be in the past
+    if ticks() >= self.deadline {
+      self.unregister();
+      return Poll::Ready(());
+    }
+
+    if !self.register(cx.waker()) {
+      // no room to wait for the timer, try again on the next run
+      cx.waker().wake_by_ref();
+    }
+
+    // a tick may have passed before we got registered
+    if ticks() >= self.deadline {
+      self.unregister();
+      return Poll::Ready(());
+    }
+
+    Poll::Pending
+  }
+}
+
+impl Drop for Sleep {
+  fn drop(&mut self) {
+    self.unregister();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::interrupts::sleep_ticks;
+  use futures_util::task::noop_waker_ref;
+
+  #[test_case]
+  fn test_sleep_elapsed() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut future = sleep(0);
+
+    assert_eq!(Pin::new(&mut future).poll(&mut ctx), Poll::Ready(()));
+  }
+
+  #[test_case]
+  fn test_sleep() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut future = sleep(2);
+
+    assert_eq!(Pin::new(&mut future).poll(&mut ctx), Poll::Pending);
+    sleep_ticks(2);
+    assert_eq!(Pin::new(&mut future).poll(&mut ctx), Poll::Ready(()));
+  }
+
+  #[test_case]
+  fn test_sleep_poll_again() {
+    let mut ctx = Context::from_waker(noop_waker_ref());
+    let mut future = sleep(1000);
+
+    assert_eq!(Pin::new(&mut future).poll(&mut ctx), Poll::Pending);
+    let slot = future.slot.unwrap();
+    // polling with the same waker keeps the registration
+    assert_eq!(Pin::new(&mut future).poll(&mut ctx), Poll::Pending);
+    assert_eq!(future.slot, Some(slot));
+
+    drop(future);
+    assert!(SLEEPERS.lock()[slot].is_none());
+  }
+}
@@ -2,44 +2,519 @@
 //! of a kernel
 
 use crate::{serial_print, serial_println};
+use core::arch::asm;
+use core::fmt::{self, Write};
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
 /// Test panic handler that prints
 /// information to the serial port
+///
+/// If the panic happens inside [catch_panic], the message is recorded and
+/// execution resumes there instead.
+///
+/// Otherwise the message is printed on a line of its own as
+/// `PANIC_MSG: <message>`, for the host to grep the serial output for.
+/// Then the hook installed with [set_panic_hook] runs if there is one.
+/// If there isn't, the failure is reported like a failed test of
+/// [test_runner], and QEMU exits with [QemuExitCode::Failed].
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
-  serial_println!("[failed]\n");
-  serial_println!("Error: {}\n", info);
+  PANIC_MESSAGE.lock().record(info);
+
+  if CATCHING.swap(false, Ordering::SeqCst) {
+    unsafe { jump_back() }
+  }
+
+  serial_println!("PANIC_MSG: {}", info.message());
+  // release both locks before running the hook, which never returns
+  let hook = PANIC_HOOK.lock().take();
+  if let Some(hook) = hook {
+    hook(&last_panic_message());
+  }
+
+  report_failure(current_test(), &last_panic_message());
   exit_qemu(QemuExitCode::Failed);
 }
 
+/// Print the failure of test `name` to serial, including a line of the
+/// form `KTEST-FAIL: <test name> :: <message> at <location>` for CI to
+/// scrape the log for.
+fn report_failure(name: &str, message: &PanicMessage) {
+  serial_println!("[failed]\n");
+  serial_println!(
+    "KTEST-FAIL: {} :: {} at {}",
+    name,
+    message.message(),
+    message.location()
+  );
+  serial_println!("Error: {}\n", message);
+}
+
+/// Hook run by [test_panic_handler] on the next panic outside of
+/// [catch_panic].
+static PANIC_HOOK: spin::Mutex<Option<fn(&PanicMessage) -> !>> =
+  spin::Mutex::new(None);
+
+/// Install a hook that [test_panic_handler] runs with the recorded
+/// message on the next panic outside of [catch_panic], instead of
+/// failing. The hook is removed once it runs, and replaces any hook
+/// installed before.
+///
+/// This is how an integration test that expects a panic passes, e.g.,
+/// with [exit_success] as the hook. Unlike [catch_panic], it doesn't
+/// resume execution, so it also works if the panic leaves the kernel in
+/// an unusable state.
+pub fn set_panic_hook(hook: fn(&PanicMessage) -> !) {
+  *PANIC_HOOK.lock() = Some(hook);
+}
+
+/// Remove the hook installed with [set_panic_hook], if any.
+pub fn clear_panic_hook() {
+  PANIC_HOOK.lock().take();
+}
+
+/// A panic hook that reports the expected panic as passing and exits
+/// QEMU with [QemuExitCode::Success].
+pub fn exit_success(_message: &PanicMessage) -> ! {
+  serial_println!("[ok]");
+  exit_qemu(QemuExitCode::Success);
+}
+
+/// Returns the message of the most recent panic, empty if there was none.
+pub fn last_panic_message() -> PanicMessage {
+  PANIC_MESSAGE.lock().clone()
+}
+
+/// Where to resume after a panic caught by [catch_panic].
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct JumpBuffer {
+  rsp: u64,
+  rbp: u64,
+  rip: u64,
+}
+
+static mut JUMP_BUFFER: JumpBuffer = JumpBuffer {
+  rsp: 0,
+  rbp: 0,
+  rip: 0,
+};
+
+/// Whether [JUMP_BUFFER] is armed by [catch_panic].
+static CATCHING: AtomicBool = AtomicBool::new(false);
+
+/// Message of the most recent panic.
+static PANIC_MESSAGE: spin::Mutex<PanicMessage> =
+  spin::Mutex::new(PanicMessage::new());
+
+/// The message and location of a panic recorded by [test_panic_handler].
+/// They are stored in a fixed-size buffer, as the heap might not be
+/// available, so long messages are truncated.
+#[derive(Clone)]
+pub struct PanicMessage {
+  /// The location followed by the message
+  buf: [u8; 256],
+  /// Length of the location at the start of `buf`
+  location_len: usize,
+  len: usize,
+}
+
+impl PanicMessage {
+  const fn new() -> Self {
+    Self {
+      buf: [0; 256],
+      location_len: 0,
+      len: 0,
+    }
+  }
+
+  /// Replace the recorded panic with the one described by `info`.
+  fn record(&mut self, info: &PanicInfo) {
+    self.len = 0;
+    let _ = match info.location() {
+      Some(location) => write!(self, "{}", location),
+      None => write!(self, "<unknown location>"),
+    };
+    self.location_len = self.len;
+    let _ = write!(self, "{}", info.message());
+  }
+
+  /// Returns the message, without the location, as a string slice.
+  pub fn as_str(&self) -> &str {
+    self.message()
+  }
+
+  /// Returns the message the panic was raised with.
+  pub fn message(&self) -> &str {
+    valid_prefix(&self.buf[self.location_len..self.len])
+  }
+
+  /// Returns where the panic was raised, as `file:line:column`.
+  pub fn location(&self) -> &str {
+    valid_prefix(&self.buf[..self.location_len])
+  }
+}
+
+/// The longest prefix of `bytes` that is valid UTF-8, as truncation
+/// might have split a character.
+fn valid_prefix(bytes: &[u8]) -> &str {
+  match core::str::from_utf8(bytes) {
+    Ok(s) => s,
+    Err(e) => core::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap(),
+  }
+}
+
+impl fmt::Debug for PanicMessage {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fmt::Debug::fmt(self.message(), f)
+  }
+}
+
+/// Formats the panic like the [PanicInfo] it was recorded from.
+impl fmt::Display for PanicMessage {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "panicked at {}:\n{}", self.location(), self.message())
+  }
+}
+
+impl Write for PanicMessage {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    let n = s.len().min(self.buf.len() - self.len);
+    self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+    self.len += n;
+    Ok(())
+  }
+}
+
+/// Calls the closure behind `f`, used by [catch_panic] as it can't call
+/// a closure from assembly directly.
+extern "C" fn call_closure(f: *const &dyn Fn()) {
+  unsafe { (*f)() }
+}
+
+/// Resume execution in [catch_panic] from [JUMP_BUFFER].
+///
+/// # Safety
+/// [JUMP_BUFFER] must be armed by a [catch_panic] call that is still
+/// running.
+unsafe fn jump_back() -> ! {
+  unsafe {
+    asm!(
+      "mov rsp, [{0}]",
+      "mov rbp, [{0} + 8]",
+      "jmp [{0} + 16]",
+      in(reg) core::ptr::addr_of!(JUMP_BUFFER),
+      options(noreturn)
+    );
+  }
+}
+
+/// Run `f` and return the panic message if it panicked.
+///
+/// There is no unwinding, so on a panic the rest of `f` is abandoned
+/// without running destructors, and locks held by it stay locked. The
+/// interrupt flag is restored to its state before the call though, along
+/// with the count of [Locked](crate::allocator::Locked) guards, as the
+/// skipped destructors may include guards that would have re-enabled
+/// interrupts. Calls can be nested, a panic is caught by the innermost one.
+// the message is returned by value, it's only used in tests
+#[allow(clippy::result_large_err)]
+pub fn catch_panic(f: &dyn Fn()) -> Result<(), PanicMessage> {
+  use x86_64::instructions::interrupts;
+
+  // save the enclosing catch_panic's state, restored when returning
+  let outer = unsafe { core::ptr::addr_of!(JUMP_BUFFER).read() };
+  let outer_catching = CATCHING.swap(true, Ordering::SeqCst);
+  let were_enabled = interrupts::are_enabled();
+  let lock_depth = crate::allocator::lock_depth();
+
+  let panicked: u64;
+  unsafe {
+    asm!(
+      // save the callee-saved registers, which aren't restored by a jump back
+      "push rbx",
+      "push rbp",
+      "push r12",
+      "push r13",
+      "push r14",
+      "push r15",
+      // keep the unaligned stack pointer in rbp, and align the stack
+      // for the call
+      "mov rbp, rsp",
+      "and rsp, -16",
+      "mov [rsi], rsp",
+      "mov [rsi + 8], rbp",
+      "lea rax, [rip + 2f]",
+      "mov [rsi + 16], rax",
+      "call {call}",
+      "xor eax, eax",
+      "jmp 3f",
+      // jump_back lands here, with rsp and rbp restored
+      "2:",
+      "mov eax, 1",
+      "3:",
+      "mov rsp, rbp",
+      "pop r15",
+      "pop r14",
+      "pop r13",
+      "pop r12",
+      "pop rbp",
+      "pop rbx",
+      call = sym call_closure,
+      inout("rdi") &f as *const &dyn Fn() => _,
+      inout("rsi") core::ptr::addr_of_mut!(JUMP_BUFFER) => _,
+      out("rax") panicked,
+      clobber_abi("C"),
+    );
+  }
+
+  unsafe { core::ptr::addr_of_mut!(JUMP_BUFFER).write(outer) };
+  CATCHING.store(outer_catching, Ordering::SeqCst);
+
+  if panicked == 0 {
+    return Ok(());
+  }
+  // the guards `f` abandoned are never dropped
+  unsafe { crate::allocator::reset_lock_depth(lock_depth) };
+  if were_enabled {
+    interrupts::enable();
+  } else {
+    interrupts::disable();
+  }
+  Err(PANIC_MESSAGE.lock().clone())
+}
+
+/// Run `f` and make sure it panics with a message containing
+/// `expected_msg`, panicking otherwise. See [catch_panic] for the
+/// limitations.
+pub fn should_panic(f: impl Fn(), expected_msg: &str) {
+  match catch_panic(&f) {
+    Err(message) if message.as_str().contains(expected_msg) => {}
+    Err(message) => panic!("unexpected panic: {}", message),
+    Ok(()) => panic!("test did not panic"),
+  }
+}
+
 /// Testable Trait used for printing test information
 /// in a unified way
 pub trait Testable {
-  /// Run the test function surrounded by informative logs
-  fn run(&self);
+  /// Run the test function surrounded by informative logs,
+  /// returning whether it passed
+  fn run(&self) -> bool;
 }
 
 impl<T> Testable for T
 where
   T: Fn(),
 {
-  fn run(&self) {
-    serial_print!("{}...\t", core::any::type_name::<T>());
-    self();
-    serial_println!("[ok]");
+  fn run(&self) -> bool {
+    let name = core::any::type_name::<T>();
+    serial_print!("{}...\t", name);
+    *CURRENT_TEST.lock() = name;
+    arm_timeout();
+    let result = catch_panic(self);
+    disarm_timeout();
+    *CURRENT_TEST.lock() = "";
+
+    match result {
+      Ok(()) => {
+        serial_println!("[ok]");
+        true
+      }
+      Err(message) => {
+        report_failure(name, &message);
+        false
+      }
+    }
+  }
+}
+
+/// Time a single test may run before it's reported as timed out. This
+/// relies on timer interrupts, so it only works after [crate::init].
+///
+/// A test that hangs with interrupts disabled never sees the deadline
+/// pass. That includes a deadlock on a [Locked](crate::allocator::Locked)
+/// lock like the heap allocator's, which spins with interrupts disabled,
+/// so only the `test-timeout` of bootimage ends such a run.
+pub const TEST_TIMEOUT_MS: u64 = 10_000;
+
+/// Tick at which the running test times out, `u64::MAX` if none is running.
+static TEST_DEADLINE: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// Name of the running test, set by [test_runner] and reported on
+/// failures and timeouts.
+static CURRENT_TEST: spin::Mutex<&'static str> = spin::Mutex::new("");
+
+/// Returns the name of the running test, or `<unknown>` outside of
+/// [test_runner].
+fn current_test() -> &'static str {
+  match CURRENT_TEST.try_lock().map(|name| *name) {
+    Some(name) if !name.is_empty() => name,
+    _ => "<unknown>",
+  }
+}
+
+fn arm_timeout() {
+  use crate::interrupts::{ms_to_ticks, set_tick_hook, ticks};
+
+  set_tick_hook(check_timeout);
+  TEST_DEADLINE.store(ticks() + ms_to_ticks(TEST_TIMEOUT_MS), Ordering::SeqCst);
+}
+
+fn disarm_timeout() {
+  TEST_DEADLINE.store(u64::MAX, Ordering::SeqCst);
+}
+
+/// Run on every timer tick, exits QEMU with [QemuExitCode::Failed] if
+/// the running test has run past its deadline.
+fn check_timeout(now: u64) {
+  if now < TEST_DEADLINE.load(Ordering::Relaxed) {
+    return;
   }
+  disarm_timeout();
+
+  let name = current_test();
+  serial_println!("[timeout]\n");
+  serial_println!(
+    "KTEST-FAIL: {} :: did not finish in {} ms",
+    name,
+    TEST_TIMEOUT_MS
+  );
+  serial_println!("Error: {} did not finish in {} ms\n", name, TEST_TIMEOUT_MS);
+  exit_qemu(QemuExitCode::Failed);
 }
 
 /// Test runner
+///
+/// A failing test doesn't stop the run, QEMU exits with
+/// [QemuExitCode::Failed] after all tests are run.
 pub fn test_runner(tests: &[&dyn Testable]) {
   serial_println!("Running {} tests", tests.len());
+  let mut failed = 0;
   for test in tests {
-    // just run each test function
-    test.run();
+    if !test.run() {
+      failed += 1;
+    }
+  }
+
+  serial_println!(
+    "\nTest result: {} passed; {} failed",
+    tests.len() - failed,
+    failed
+  );
+  if failed > 0 {
+    exit_qemu(QemuExitCode::Failed);
+  }
+}
+
+/// Run `f` and print the number of timer ticks it took to serial as
+/// `name: N ticks`, returning the number. Ticks only advance after
+/// [crate::init], and are coarse, so `f` should run for a while.
+///
+/// Once [crate::cpu::calibrate_tsc] has run, the time measured with the
+/// time stamp counter is printed as well.
+pub fn bench(name: &str, f: impl FnOnce()) -> u64 {
+  use crate::cpu::{cycles_to_ns, rdtsc};
+  use crate::interrupts::ticks;
+
+  let start = ticks();
+  let start_cycles = rdtsc();
+  f();
+  let cycles = rdtsc() - start_cycles;
+  let elapsed = ticks() - start;
+  if let Some(ns) = cycles_to_ns(cycles) {
+    serial_println!("{}: {} ticks (~{} us)", name, elapsed, ns / 1000);
+  } else {
+    serial_println!("{}: {} ticks", name, elapsed);
   }
+  elapsed
+}
+
+/// Benchable Trait, the counterpart of [Testable] for benchmarks
+pub trait Benchable {
+  /// Run the benchmark, reporting its duration
+  fn run(&self) -> u64;
+}
+
+impl<T> Benchable for T
+where
+  T: Fn(),
+{
+  fn run(&self) -> u64 {
+    bench(core::any::type_name::<T>(), self)
+  }
+}
+
+/// Benchmark runner, to be used as the `test_runner` of benchmark
+/// binaries instead of [test_runner]
+pub fn bench_runner(benches: &[&dyn Benchable]) {
+  if let Some(frequency) = crate::cpu::calibrate_tsc() {
+    serial_println!("TSC runs at ~{} MHz", frequency / 1_000_000);
+  }
+  serial_println!("Running {} benchmarks", benches.len());
+  let total: u64 = benches.iter().map(|bench| bench.run()).sum();
+  serial_println!("\nBench result: {} ticks in total", total);
+}
+
+/// Assert that a boolean expression is true, printing the expression and
+/// its source location to serial if it isn't.
+///
+/// The failure then panics like `assert!`, which fails the running test,
+/// or exits QEMU with [QemuExitCode::Failed] outside of the test runner.
+#[macro_export]
+macro_rules! kassert {
+  ($cond:expr $(,)?) => {
+    if !$cond {
+      $crate::test_harness::assertion_failed(
+        file!(),
+        line!(),
+        format_args!("kassert!({})", stringify!($cond)),
+      );
+    }
+  };
+}
+
+/// Assert that two expressions are equal, printing the expressions, both
+/// values and the source location to serial if they aren't.
+///
+/// See [kassert!] for how the failure is handled.
+#[macro_export]
+macro_rules! kassert_eq {
+  ($left:expr, $right:expr $(,)?) => {
+    match (&$left, &$right) {
+      (left, right) => {
+        if !(*left == *right) {
+          $crate::test_harness::assertion_failed(
+            file!(),
+            line!(),
+            format_args!(
+              "kassert_eq!({}, {})\n  left: {:?}\n right: {:?}",
+              stringify!($left),
+              stringify!($right),
+              left,
+              right
+            ),
+          );
+        }
+      }
+    }
+  };
+}
+
+/// Report a failed [kassert!] or [kassert_eq!] and panic.
+#[doc(hidden)]
+pub fn assertion_failed(file: &str, line: u32, args: fmt::Arguments) -> ! {
+  serial_println!("\n{}:{}: assertion failed: {}", file, line, args);
+  panic!("assertion failed at {}:{}", file, line);
 }
 
 /// Qemu Exit Code
+///
+/// This is the only definition, the binaries and integration tests use
+/// it through this module. QEMU exits with the status `(code << 1) | 1`,
+/// so the host runner sees 33 for [QemuExitCode::Success], which is the
+/// `test-success-exit-code` in Cargo.toml, and 35 for
+/// [QemuExitCode::Failed].
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u32)]
 pub enum QemuExitCode {
@@ -49,14 +524,87 @@ pub enum QemuExitCode {
   Failed = 0x11,
 }
 
-/// Exit qemu by writing to the debug exit port
-/// Node: the port should correspond to configuration in Cargo.toml
+/// I/O port of QEMU's `isa-debug-exit` device, which must match the
+/// `iobase` in the QEMU arguments in Cargo.toml.
+///
+/// It defaults to `0xf4` and can be overridden at build time with the
+/// `QEMU_EXIT_PORT` environment variable, e.g. `QEMU_EXIT_PORT=0x501`.
+pub const EXIT_PORT: u16 = match option_env!("QEMU_EXIT_PORT") {
+  Some(port) => parse_port(port),
+  None => 0xf4,
+};
+
+/// Parse a decimal or `0x`-prefixed hexadecimal port number at compile time.
+const fn parse_port(s: &str) -> u16 {
+  let bytes = s.as_bytes();
+  let (radix, mut i) =
+    if bytes.len() > 2 && bytes[0] == b'0' && bytes[1] == b'x' {
+      (16, 2)
+    } else {
+      (10, 0)
+    };
+  assert!(i < bytes.len(), "QEMU_EXIT_PORT is empty");
+
+  let mut port: u32 = 0;
+  while i < bytes.len() {
+    let digit = match bytes[i] {
+      b'0'..=b'9' => bytes[i] - b'0',
+      b'a'..=b'f' if radix == 16 => bytes[i] - b'a' + 10,
+      b'A'..=b'F' if radix == 16 => bytes[i] - b'A' + 10,
+      _ => panic!("QEMU_EXIT_PORT is not a valid number"),
+    };
+    port = port * radix + digit as u32;
+    assert!(port <= u16::MAX as u32, "QEMU_EXIT_PORT is out of range");
+    i += 1;
+  }
+  port as u16
+}
+
+/// Exit qemu by writing to the debug exit port at [EXIT_PORT]
 pub fn exit_qemu(exit_code: QemuExitCode) -> ! {
+  exit_qemu_with_port(EXIT_PORT, exit_code)
+}
+
+/// Exit qemu by writing to an `isa-debug-exit` device at the given port
+pub fn exit_qemu_with_port(port: u16, exit_code: QemuExitCode) -> ! {
   use x86_64::instructions::port::Port;
-  let mut port = Port::new(0xf4);
+  let mut port = Port::new(port);
   loop {
     unsafe {
       port.write(exit_code as u32);
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test_case]
+  fn test_parse_port() {
+    assert_eq!(parse_port("0xf4"), 0xf4);
+    assert_eq!(parse_port("0x501"), 0x501);
+    assert_eq!(parse_port("244"), 0xf4);
+  }
+
+  #[test_case]
+  fn test_kassert_eq_fails() {
+    should_panic(|| kassert_eq!(1 + 1, 3), "assertion failed at");
+  }
+
+  #[test_case]
+  fn test_catch_panic_message() {
+    let result = catch_panic(&|| panic!("caught {}", 42));
+    assert!(result.unwrap_err().as_str().contains("caught 42"));
+    assert!(last_panic_message().as_str().contains("caught 42"));
+    assert!(last_panic_message().location().starts_with(file!()));
+  }
+
+  #[test_case]
+  fn test_clear_panic_hook() {
+    set_panic_hook(exit_success);
+    assert!(PANIC_HOOK.lock().is_some());
+    clear_panic_hook();
+    assert!(PANIC_HOOK.lock().is_none());
+  }
+}
@@ -1,20 +1,41 @@
 //! This module provides abstraction over the VGA text buffer
 //! to provides utilities of printing, etc.
 
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
 use core::result::Result::Ok;
-use spin::Mutex;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::{Mutex, MutexGuard};
 use volatile::Volatile;
 
 lazy_static! {
     /// Global writer instance that drives the VGA buffer.
-    static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
-        column_position: 0,
-        color_code: ColorCode::new(Color::Yellow, Color::Black),
-        /// we know that the buffer locates at memory-mapped address 0xb8000
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
-    });
+    static ref WRITER: Mutex<Writer> = {
+        let mut writer = Writer {
+            column_position: 0,
+            color_code: ColorCode::new(Color::Yellow, Color::Black),
+            scroll_top: 0,
+            scroll_bottom: BUFFER_HEIGHT,
+            shadow: [[ScreenChar {
+                ascii_character: b' ',
+                color_code: ColorCode(0),
+            }; BUFFER_WIDTH]; BUFFER_HEIGHT],
+            dirty_rows: [false; BUFFER_HEIGHT],
+            buffered: false,
+            scrollback: None,
+            view_offset: 0,
+            // we know that the buffer locates at memory-mapped address 0xb8000
+            buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
+        };
+        // start from whatever is already on the screen
+        writer.load_shadow();
+        Mutex::new(writer)
+    };
 }
 
+/// Whether VGA output is also echoed to the serial port.
+static MIRROR_TO_SERIAL: AtomicBool = AtomicBool::new(false);
+
 /// Print to the global VGA buffer writer
 #[macro_export]
 macro_rules! print {
@@ -28,6 +49,25 @@ macro_rules! println {
     ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
 }
 
+/// Print to the global VGA buffer writer in the given colors,
+/// e.g., `cprint!(Color::Red, Color::Black, "{}", x)`.
+/// The writer's previous color is restored afterwards.
+#[macro_export]
+macro_rules! cprint {
+    ($fg:expr, $bg:expr, $($arg:tt)*) => (
+      $crate::vga_buffer::_cprint($fg, $bg, format_args!($($arg)*))
+    );
+}
+
+/// Colored print ending with newline
+#[macro_export]
+macro_rules! cprintln {
+    ($fg:expr, $bg:expr) => ($crate::cprint!($fg, $bg, "\n"));
+    ($fg:expr, $bg:expr, $($arg:tt)*) => (
+      $crate::cprint!($fg, $bg, "{}\n", format_args!($($arg)*))
+    );
+}
+
 #[doc(hidden)]
 pub fn _print(args: core::fmt::Arguments) {
   use core::fmt::Write;
@@ -35,36 +75,520 @@ pub fn _print(args: core::fmt::Arguments) {
 
   // If an interrupt occurs when we hold this lock, and the handler
   // tries to print something, then the system would deadlock.
+  // Exceptions can't be masked this way, so a panic while printing
+  // still leaves the lock held, see [force_unlock].
   interrupts::without_interrupts(|| {
     WRITER.lock().write_fmt(args).unwrap();
+    mirror_to_serial(args);
+  });
+}
+
+/// Print to the global VGA buffer writer unless it's locked, in which case
+/// the output is dropped. Returns whether it was printed.
+///
+/// Unlike [print!](crate::print) this never blocks, so it's safe to use in interrupt
+/// handlers and on the panic path, where waiting for the lock could
+/// deadlock. See [serial::try_serial_print](crate::serial::try_serial_print)
+/// for the serial port.
+pub fn try_print(args: core::fmt::Arguments) -> bool {
+  use core::fmt::Write;
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| match WRITER.try_lock() {
+    Some(mut writer) => {
+      writer.write_fmt(args).unwrap();
+      drop(writer);
+      if MIRROR_TO_SERIAL.load(Ordering::Relaxed) {
+        crate::serial::try_serial_print(args);
+      }
+      true
+    }
+    None => false,
+  })
+}
+
+#[doc(hidden)]
+pub fn _cprint(
+  foreground: Color,
+  background: Color,
+  args: core::fmt::Arguments,
+) {
+  use core::fmt::Write;
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    let mut writer = WRITER.lock();
+    let old_color = writer.color_code;
+    writer.set_color(foreground, background);
+    // restore the old color before reporting any formatting error,
+    // so a failed write never leaks the temporary color.
+    let result = writer.write_fmt(args);
+    writer.color_code = old_color;
+    result.unwrap();
+    drop(writer);
+    mirror_to_serial(args);
+  });
+}
+
+/// Echo `args` to the serial port if mirroring is enabled.
+///
+/// Must be called with interrupts disabled and without holding the
+/// [WRITER] lock, so that the output can't interleave with other prints.
+fn mirror_to_serial(args: core::fmt::Arguments) {
+  use core::fmt::Write;
+
+  if MIRROR_TO_SERIAL.load(Ordering::Relaxed) {
+    crate::serial::SERIAL1.lock().write_fmt(args).unwrap();
+  }
+}
+
+/// Enable or disable echoing everything printed to the VGA buffer
+/// to the serial port as well, e.g., to capture output in CI logs.
+pub fn set_mirror(mirror: bool) {
+  MIRROR_TO_SERIAL.store(mirror, Ordering::Relaxed);
+}
+
+/// Set the foreground and background color used by the global
+/// VGA buffer writer for all subsequent output.
+pub fn set_color(foreground: Color, background: Color) {
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    WRITER.lock().set_color(foreground, background);
   });
 }
 
+/// Switch the global VGA buffer writer between buffered and immediate mode.
+///
+/// In buffered mode output only lands in an off-screen buffer and becomes
+/// visible on the next [flush], which makes heavy scrolling much cheaper.
+/// Leaving buffered mode flushes all pending output.
+pub fn set_buffered(buffered: bool) {
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    WRITER.lock().set_buffered(buffered);
+  });
+}
+
+/// Copy all pending output of the global VGA buffer writer to the screen.
+pub fn flush() {
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    WRITER.lock().flush();
+  });
+}
+
+/// Clear the scroll region of the global VGA buffer, see [Writer::clear].
+/// Rows pinned outside of it, like the [status_bar], are kept.
+pub fn clear_screen() {
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    WRITER.lock().clear();
+  });
+}
+
+/// Start keeping the last [SCROLLBACK_ROWS] rows that scroll off the
+/// global VGA buffer, so that [scroll_up] can bring them back.
+///
+/// The history lives on the heap, so this returns false without enabling
+/// anything if [init_heap](crate::allocator::init_heap) hasn't run yet or
+/// the history doesn't fit on the heap. Enabling it again keeps the
+/// existing history.
+pub fn enable_scrollback() -> bool {
+  use x86_64::instructions::interrupts;
+
+  if !crate::allocator::heap_initialized() {
+    return false;
+  }
+  if interrupts::without_interrupts(|| WRITER.lock().scrollback.is_some()) {
+    return true;
+  }
+  // allocate without holding the lock, which keeps interrupts disabled
+  let scrollback = match Scrollback::new(SCROLLBACK_ROWS) {
+    Some(scrollback) => scrollback,
+    None => return false,
+  };
+  interrupts::without_interrupts(|| {
+    WRITER.lock().scrollback.get_or_insert(scrollback);
+  });
+  true
+}
+
+/// Scroll the global VGA buffer back by `lines` rows of history, as far
+/// as the history goes. Does nothing unless [enable_scrollback] was
+/// called. See [Writer::scroll_up].
+pub fn scroll_up(lines: usize) {
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    WRITER.lock().scroll_up(lines);
+  });
+}
+
+/// Scroll the global VGA buffer forward by `lines` rows, back towards the
+/// live output.
+pub fn scroll_down(lines: usize) {
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    WRITER.lock().scroll_down(lines);
+  });
+}
+
+/// Show the live output of the global VGA buffer again after scrolling
+/// back with [scroll_up].
+pub fn scroll_to_bottom() {
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    WRITER.lock().scroll_to_bottom();
+  });
+}
+
+/// Write `bytes` to the global VGA buffer unchanged, as code page 437
+/// glyphs, e.g., for box drawing characters. Only `\n` starts a new line,
+/// see [Writer::write_raw_byte].
+///
+/// The bytes aren't mirrored to the serial port, as they're generally not
+/// valid text.
+pub fn write_raw(bytes: &[u8]) {
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    WRITER.lock().write_raw(bytes);
+  });
+}
+
+/// Number of bytes shown per row by [HexDump]
+const HEXDUMP_ROW_LEN: usize = 16;
+
+/// Formats bytes in the layout of `xxd`: rows of 16 bytes with the
+/// offset on the left, the bytes in hex in groups of two, and the bytes
+/// as ASCII on the right, where non-printable ones show as `.`.
+pub struct HexDump<'a>(pub &'a [u8]);
+
+impl core::fmt::Display for HexDump<'_> {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    for (row, bytes) in self.0.chunks(HEXDUMP_ROW_LEN).enumerate() {
+      write!(f, "{:08x}:", row * HEXDUMP_ROW_LEN)?;
+      for i in 0..HEXDUMP_ROW_LEN {
+        if i % 2 == 0 {
+          f.write_str(" ")?;
+        }
+        match bytes.get(i) {
+          Some(byte) => write!(f, "{:02x}", byte)?,
+          // pad the last row so the ASCII column lines up
+          None => f.write_str("  ")?,
+        }
+      }
+      f.write_str("  ")?;
+      for &byte in bytes {
+        let c = if byte.is_ascii_graphic() || byte == b' ' {
+          byte as char
+        } else {
+          '.'
+        };
+        write!(f, "{}", c)?;
+      }
+      writeln!(f)?;
+    }
+    Ok(())
+  }
+}
+
+/// Print `len` bytes starting at `addr` to the VGA buffer, formatted
+/// by [HexDump]. See [serial::hexdump](crate::serial::hexdump) for the
+/// serial port.
+///
+/// # Safety
+/// The caller must guarantee that all `len` bytes from `addr` are mapped
+/// and readable, reading unmapped memory causes a page fault.
+pub unsafe fn hexdump(addr: *const u8, len: usize) {
+  let bytes = unsafe { core::slice::from_raw_parts(addr, len) };
+  crate::print!("{}", HexDump(bytes));
+}
+
+/// Write `s` at a fixed position of the global VGA buffer,
+/// see [Writer::write_at].
+pub fn write_at(row: usize, col: usize, s: &str) {
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    WRITER.lock().write_at(row, col, s);
+  });
+}
+
+/// Outline a rectangle on the global VGA buffer, see [Writer::draw_box].
+pub fn draw_box(top: usize, left: usize, height: usize, width: usize) {
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    WRITER.lock().draw_box(top, left, height, width);
+  });
+}
+
+/// Restrict scrolling of the global VGA buffer to rows `top..bottom`,
+/// leaving the rows outside of the region untouched.
+pub fn set_scroll_region(top: usize, bottom: usize) {
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    WRITER.lock().set_scroll_region(top, bottom);
+  });
+}
+
+/// Show `text` on a status bar in the top row of the global VGA buffer,
+/// see [Writer::status_bar].
+pub fn status_bar(text: &str) {
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    WRITER.lock().status_bar(text);
+  });
+}
+
+/// Read back the character at (`row`, `col`) of the global VGA buffer,
+/// together with its (foreground, background) colors.
+pub fn read_char(row: usize, col: usize) -> (u8, (Color, Color)) {
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| WRITER.lock().read_char(row, col))
+}
+
+/// The content of a single cell as returned by [snapshot]: the code page 437
+/// byte and the color code byte, whose layout is <background>|<foreground>.
+pub type Cell = (u8, u8);
+
+/// Capture what is currently shown on the screen, row by row.
+///
+/// Unlike [read_char], this reads the VGA buffer itself, so output that
+/// hasn't been flushed in buffered mode is not part of the snapshot.
+pub fn snapshot() -> [[Cell; BUFFER_WIDTH]; BUFFER_HEIGHT] {
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| WRITER.lock().snapshot())
+}
+
+/// Replace the whole screen with a red panic screen showing the message
+/// and location of the panic.
+///
+/// Meant to be called from the panic handler: it doesn't allocate, and it
+/// takes the writer over even if the panicking code was holding its lock.
+pub fn panic_screen(info: &PanicInfo) {
+  use core::fmt::Write;
+  use x86_64::instructions::interrupts;
+
+  interrupts::disable();
+  let mut writer = lock_for_panic();
+  writer.set_buffered(true);
+  writer.set_scroll_region(0, BUFFER_HEIGHT);
+  writer.set_color(Color::White, Color::Red);
+  for row in 0..BUFFER_HEIGHT {
+    writer.clear_row(row);
+  }
+  writer.column_position = 0;
+
+  let title = "KERNEL PANIC";
+  writer.write_at(1, (BUFFER_WIDTH - title.len()) / 2, title);
+  writer.draw_box(3, 0, BUFFER_HEIGHT - 3, BUFFER_WIDTH);
+
+  // the box leaves one blank column of padding on either side
+  let mut text = PanelWriter {
+    writer: &mut writer,
+    row: 4,
+    col: 2,
+    left: 2,
+    right: BUFFER_WIDTH - 2,
+    bottom: BUFFER_HEIGHT - 1,
+  };
+  let _ = write!(text, "{}", info.message());
+  if let Some(location) = info.location() {
+    let _ = write!(text, "\n\nat {}", location);
+  }
+
+  writer.set_buffered(false);
+  disable_cursor();
+}
+
+/// Lock the global writer, forcibly releasing the lock if it's held.
+///
+/// This is only sound if the holder will never run again, which is the
+/// case when the kernel panicked while printing.
+fn lock_for_panic() -> MutexGuard<'static, Writer> {
+  if let Some(writer) = WRITER.try_lock() {
+    return writer;
+  }
+  unsafe { force_unlock() };
+  WRITER.lock()
+}
+
+/// Forcibly release the lock of the global VGA buffer writer, so that
+/// printing works again after the kernel panicked while holding it.
+/// [panic_screen] already does this by itself.
+///
+/// # Safety
+///
+/// Whoever holds the lock must never use the writer again, e.g. because
+/// it's the code that panicked. Otherwise two parties access the writer
+/// at the same time.
+pub unsafe fn force_unlock() {
+  unsafe { WRITER.force_unlock() };
+}
+
+/// Writes text into the rectangle of columns `left..right` and rows up to
+/// `bottom` (exclusive), wrapping long lines and dropping whatever doesn't
+/// fit.
+struct PanelWriter<'a> {
+  writer: &'a mut Writer,
+  row: usize,
+  col: usize,
+  left: usize,
+  right: usize,
+  bottom: usize,
+}
+
+impl core::fmt::Write for PanelWriter<'_> {
+  fn write_str(&mut self, s: &str) -> core::fmt::Result {
+    for c in s.chars() {
+      if c == '\n' || self.col == self.right {
+        self.row += 1;
+        self.col = self.left;
+        if c == '\n' {
+          continue;
+        }
+      }
+      if self.row >= self.bottom {
+        break;
+      }
+      self
+        .writer
+        .write_at(self.row, self.col, c.encode_utf8(&mut [0; 4]));
+      self.col += 1;
+    }
+    Ok(())
+  }
+}
+
+/// Show the blinking hardware cursor as an underline
+/// at the bottom of the character cell.
+pub fn enable_cursor() {
+  // bit 5 of the cursor start register disables the cursor,
+  // and the low 5 bits of both registers select the scanlines.
+  let start = read_crtc(CRTC_CURSOR_START) & 0xc0;
+  write_crtc(CRTC_CURSOR_START, start | CURSOR_START_SCANLINE);
+  let end = read_crtc(CRTC_CURSOR_END) & 0xe0;
+  write_crtc(CRTC_CURSOR_END, end | CURSOR_END_SCANLINE);
+}
+
+/// Hide the blinking hardware cursor.
+pub fn disable_cursor() {
+  write_crtc(CRTC_CURSOR_START, 0x20);
+}
+
+/// Write `value` to the CRT controller register `index`.
+fn write_crtc(index: u8, value: u8) {
+  use x86_64::instructions::port::Port;
+
+  let mut index_port: Port<u8> = Port::new(CRTC_INDEX_PORT);
+  let mut data_port: Port<u8> = Port::new(CRTC_DATA_PORT);
+  unsafe {
+    index_port.write(index);
+    data_port.write(value);
+  }
+}
+
+/// Read the CRT controller register `index`.
+fn read_crtc(index: u8) -> u8 {
+  use x86_64::instructions::port::Port;
+
+  let mut index_port: Port<u8> = Port::new(CRTC_INDEX_PORT);
+  let mut data_port: Port<u8> = Port::new(CRTC_DATA_PORT);
+  unsafe {
+    index_port.write(index);
+    data_port.read()
+  }
+}
+
+/// The CRT controller is programmed by writing a register index to
+/// the index port, and then reading or writing the data port.
+const CRTC_INDEX_PORT: u16 = 0x3d4;
+const CRTC_DATA_PORT: u16 = 0x3d5;
+/// CRTC registers that control the shape and location of the cursor
+const CRTC_CURSOR_START: u8 = 0x0a;
+const CRTC_CURSOR_END: u8 = 0x0b;
+const CRTC_CURSOR_LOCATION_HIGH: u8 = 0x0e;
+const CRTC_CURSOR_LOCATION_LOW: u8 = 0x0f;
+/// Scanlines of the character cell covered by the cursor
+const CURSOR_START_SCANLINE: u8 = 14;
+const CURSOR_END_SCANLINE: u8 = 15;
+
 /// Represents the color recognized by VGA
 /// Each color occupies at most the small four bits
 /// of the underlieing u8
-#[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
-enum Color {
+pub enum Color {
+  /// Black
   Black = 0,
+  /// Blue
   Blue = 1,
+  /// Green
   Green = 2,
+  /// Cyan
   Cyan = 3,
+  /// Red
   Red = 4,
+  /// Magenta
   Magenta = 5,
+  /// Brown
   Brown = 6,
+  /// Light gray
   LightGray = 7,
+  /// Dark gray
   DarkGray = 8,
+  /// Light blue
   LightBlue = 9,
+  /// Light green
   LightGreen = 10,
+  /// Light cyan
   LightCyan = 11,
+  /// Light red
   LightRed = 12,
+  /// Pink
   Pink = 13,
+  /// Yellow
   Yellow = 14,
+  /// White
   White = 15,
 }
 
+impl Color {
+  /// Decode a color from the lower four bits of `value`.
+  fn from_u8(value: u8) -> Color {
+    match value & 0xf {
+      0 => Color::Black,
+      1 => Color::Blue,
+      2 => Color::Green,
+      3 => Color::Cyan,
+      4 => Color::Red,
+      5 => Color::Magenta,
+      6 => Color::Brown,
+      7 => Color::LightGray,
+      8 => Color::DarkGray,
+      9 => Color::LightBlue,
+      10 => Color::LightGreen,
+      11 => Color::LightCyan,
+      12 => Color::LightRed,
+      13 => Color::Pink,
+      14 => Color::Yellow,
+      _ => Color::White,
+    }
+  }
+}
+
 /// ColorCode represents an entire color code byte for VGA,
 /// whose layout is <background>|<foreground>.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -77,6 +601,16 @@ impl ColorCode {
     let color: u8 = (background as u8) << 4 | foreground as u8;
     Self(color)
   }
+
+  /// The foreground color stored in the lower four bits.
+  fn foreground(self) -> Color {
+    Color::from_u8(self.0)
+  }
+
+  /// The background color stored in the higher four bits.
+  fn background(self) -> Color {
+    Color::from_u8(self.0 >> 4)
+  }
 }
 
 /// ScreenChar is the entire display unit in the VGA buffer. It contains two consecutive
@@ -88,9 +622,29 @@ struct ScreenChar {
   color_code: ColorCode,
 }
 
-/// VGA text buffer has 25 rows and 80 coliumns
-const BUFFER_HEIGHT: usize = 25;
-const BUFFER_WIDTH: usize = 80;
+/// Unicode equivalents of the upper half (0x80..=0xff) of code page 437,
+/// the character set of the VGA text mode font.
+const CP437_UPPER_HALF: &str = "\
+  ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»\
+  ░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀\
+  αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+
+/// Map a non-ASCII character to its code page 437 byte, if there is one.
+fn char_to_cp437(c: char) -> Option<u8> {
+  CP437_UPPER_HALF
+    .chars()
+    .position(|u| u == c)
+    .map(|i| 0x80 + i as u8)
+}
+
+/// Number of rows of the VGA text buffer
+pub const BUFFER_HEIGHT: usize = 25;
+/// Number of columns of the VGA text buffer
+pub const BUFFER_WIDTH: usize = 80;
+/// Black on light gray, the colors of the status bar, see [status_bar]
+const STATUS_BAR_COLOR: ColorCode = ColorCode((Color::LightGray as u8) << 4);
+/// Tab stops are placed at every multiple of this many columns
+const TAB_WIDTH: usize = 4;
 
 /// Represents a VGA text buffer
 #[derive(Debug)]
@@ -101,20 +655,324 @@ struct Buffer {
   chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
 }
 
+/// Number of rows kept in the history enabled by [enable_scrollback]
+pub const SCROLLBACK_ROWS: usize = 200;
+
+/// A single row of the screen
+type Row = [ScreenChar; BUFFER_WIDTH];
+
+/// Rows that scrolled off the top of the screen, oldest first. Once all
+/// `capacity` rows are in use, each new row replaces the oldest one, so
+/// recording rows never allocates.
+#[derive(Debug)]
+struct Scrollback {
+  rows: Vec<Row>,
+  capacity: usize,
+  /// Index of the oldest row in `rows`
+  start: usize,
+}
+
+impl Scrollback {
+  /// Create an empty history of `capacity` rows, or None if the heap
+  /// doesn't have room for it.
+  ///
+  /// Panics if `capacity` is zero.
+  fn new(capacity: usize) -> Option<Self> {
+    assert!(capacity > 0, "scrollback must hold at least one row");
+    let mut rows = Vec::new();
+    rows.try_reserve_exact(capacity).ok()?;
+    Some(Scrollback {
+      rows,
+      capacity,
+      start: 0,
+    })
+  }
+
+  /// Number of rows in the history.
+  fn len(&self) -> usize {
+    self.rows.len()
+  }
+
+  /// Append `row` as the newest row, dropping the oldest one if the
+  /// history is full.
+  fn push(&mut self, row: Row) {
+    if self.rows.len() < self.capacity {
+      self.rows.push(row);
+    } else {
+      self.rows[self.start] = row;
+      self.start = (self.start + 1) % self.capacity;
+    }
+  }
+
+  /// The `index`th oldest row.
+  fn get(&self, index: usize) -> &Row {
+    &self.rows[(self.start + index) % self.rows.len()]
+  }
+}
+
 /// Writer represents a write handler to the VGA buffer.
 /// It keeps track of current position and current color to make writing
 /// to buffer easier.
+///
+/// All writes go to an off-screen shadow copy of the buffer first, and
+/// the rows they touched are copied to the VGA buffer on [Writer::flush].
+/// Unless the writer is in buffered mode, every write is flushed right away.
 #[derive(Debug)]
 struct Writer {
   /// Current cursor position
   column_position: usize,
   /// Current color
   color_code: ColorCode,
+  /// First row (inclusive) that takes part in scrolling
+  scroll_top: usize,
+  /// Last row (exclusive) that takes part in scrolling. Text is
+  /// always written to the row right above it.
+  scroll_bottom: usize,
+  /// Off-screen copy of the buffer that all writes go to
+  shadow: [[ScreenChar; BUFFER_WIDTH]; BUFFER_HEIGHT],
+  /// Rows of `shadow` that haven't been copied to the VGA buffer yet
+  dirty_rows: [bool; BUFFER_HEIGHT],
+  /// If false, flush after every write
+  buffered: bool,
+  /// Rows scrolled off the top of the scroll region, see [enable_scrollback]
+  scrollback: Option<Scrollback>,
+  /// How many rows the scroll region is scrolled back into `scrollback`,
+  /// zero while it shows the live output
+  view_offset: usize,
   /// A static reference to the buffer area
   buffer: &'static mut Buffer,
 }
 
 impl Writer {
+  /// Change the color used for subsequently written characters.
+  /// Rows cleared by scrolling are also filled with this color.
+  fn set_color(&mut self, foreground: Color, background: Color) {
+    self.color_code = ColorCode::new(foreground, background);
+  }
+
+  /// Restrict scrolling to rows `top..bottom`. Rows outside of the
+  /// region are pinned and never touched by [Writer::new_line].
+  ///
+  /// Panics if the region is empty or exceeds the buffer.
+  fn set_scroll_region(&mut self, top: usize, bottom: usize) {
+    assert!(
+      top < bottom,
+      "invalid scroll region {}..{}: top must be above bottom",
+      top,
+      bottom
+    );
+    assert!(
+      bottom <= BUFFER_HEIGHT,
+      "invalid scroll region {}..{}: VGA buffer has {} rows",
+      top,
+      bottom,
+      BUFFER_HEIGHT
+    );
+
+    // the history belongs to the old region
+    self.scroll_to_bottom();
+    self.scroll_top = top;
+    self.scroll_bottom = bottom;
+    self.update_cursor();
+  }
+
+  /// Enable or disable buffered mode, flushing pending output when
+  /// switching back to immediate mode.
+  fn set_buffered(&mut self, buffered: bool) {
+    self.buffered = buffered;
+    if !buffered {
+      self.flush();
+    }
+  }
+
+  /// Copy every dirty row of the shadow buffer to the VGA buffer. While
+  /// scrolled back, the rows of the scroll region stay dirty until the
+  /// view returns to the live output.
+  fn flush(&mut self) {
+    let scrolled_back = self.view_offset > 0;
+    for row in 0..BUFFER_HEIGHT {
+      let in_region = (self.scroll_top..self.scroll_bottom).contains(&row);
+      if !self.dirty_rows[row] || (scrolled_back && in_region) {
+        continue;
+      }
+      for (col, c) in self.shadow[row].iter().enumerate() {
+        self.buffer.chars[row][col].write(*c);
+      }
+      self.dirty_rows[row] = false;
+    }
+  }
+
+  /// Flush right away unless in buffered mode.
+  fn flush_if_unbuffered(&mut self) {
+    if !self.buffered {
+      self.flush();
+    }
+  }
+
+  /// Initialize the shadow buffer with the current screen content.
+  fn load_shadow(&mut self) {
+    for (row, chars) in self.shadow.iter_mut().enumerate() {
+      for (col, c) in chars.iter_mut().enumerate() {
+        *c = self.buffer.chars[row][col].read();
+      }
+    }
+  }
+
+  /// Write a single cell of the shadow buffer.
+  fn write_cell(&mut self, row: usize, col: usize, c: ScreenChar) {
+    self.shadow[row][col] = c;
+    self.dirty_rows[row] = true;
+  }
+
+  /// The row that text is currently written to.
+  fn current_row(&self) -> usize {
+    self.scroll_bottom - 1
+  }
+
+  /// Read the character at the given position, returning its ASCII byte
+  /// and its (foreground, background) colors.
+  ///
+  /// Panics if the position lies outside of the buffer.
+  fn read_char(&self, row: usize, col: usize) -> (u8, (Color, Color)) {
+    assert!(
+      row < BUFFER_HEIGHT,
+      "row {} out of range, VGA buffer has {} rows",
+      row,
+      BUFFER_HEIGHT
+    );
+    assert!(
+      col < BUFFER_WIDTH,
+      "column {} out of range, VGA buffer has {} columns",
+      col,
+      BUFFER_WIDTH
+    );
+
+    let screen_char = self.shadow[row][col];
+    let color_code = screen_char.color_code;
+    (
+      screen_char.ascii_character,
+      (color_code.foreground(), color_code.background()),
+    )
+  }
+
+  /// Read every cell of the VGA buffer, see [snapshot].
+  fn snapshot(&self) -> [[Cell; BUFFER_WIDTH]; BUFFER_HEIGHT] {
+    let mut cells = [[(0, 0); BUFFER_WIDTH]; BUFFER_HEIGHT];
+    for (row, chars) in cells.iter_mut().enumerate() {
+      for (col, cell) in chars.iter_mut().enumerate() {
+        let screen_char = self.buffer.chars[row][col].read();
+        *cell = (screen_char.ascii_character, screen_char.color_code.0);
+      }
+    }
+    cells
+  }
+
+  /// Write `s` in the current color starting at (`row`, `col`), without
+  /// moving the cursor or scrolling. Text that doesn't fit on the row is
+  /// truncated, and control characters are shown as `0xfe`.
+  ///
+  /// Panics if the position lies outside of the buffer.
+  fn write_at(&mut self, row: usize, col: usize, s: &str) {
+    assert!(
+      row < BUFFER_HEIGHT,
+      "row {} out of range, VGA buffer has {} rows",
+      row,
+      BUFFER_HEIGHT
+    );
+    assert!(
+      col < BUFFER_WIDTH,
+      "column {} out of range, VGA buffer has {} columns",
+      col,
+      BUFFER_WIDTH
+    );
+
+    let color_code = self.color_code;
+    for (col, c) in (col..BUFFER_WIDTH).zip(s.chars()) {
+      let ascii_character = match c {
+        ' '..='~' => c as u8,
+        c => char_to_cp437(c).unwrap_or(0xfe),
+      };
+      self.write_cell(
+        row,
+        col,
+        ScreenChar {
+          ascii_character,
+          color_code,
+        },
+      );
+    }
+
+    self.flush_if_unbuffered();
+  }
+
+  /// Replace the top row with `text` in the status bar colors, truncated
+  /// or padded with blanks to the width of the screen. The top row is
+  /// taken out of the scroll region so that it stays in place, and the
+  /// cursor position is left unchanged.
+  fn status_bar(&mut self, text: &str) {
+    if self.scroll_top == 0 {
+      self.set_scroll_region(1, self.scroll_bottom);
+    }
+
+    // compose the whole row before it reaches the screen
+    let (buffered, color_code) = (self.buffered, self.color_code);
+    self.buffered = true;
+    self.color_code = STATUS_BAR_COLOR;
+    self.clear_row(0);
+    self.write_at(0, 0, text);
+    self.color_code = color_code;
+    self.buffered = buffered;
+    self.flush_if_unbuffered();
+  }
+
+  /// Outline the rectangle of `height` rows and `width` columns whose
+  /// top-left cell is (`top`, `left`) with double-line box-drawing glyphs
+  /// in the current color. The cursor position is left unchanged.
+  ///
+  /// Panics if the box is smaller than 2x2 or doesn't fit on the screen.
+  fn draw_box(&mut self, top: usize, left: usize, height: usize, width: usize) {
+    assert!(
+      height >= 2 && width >= 2,
+      "box of {}x{} is too small, must be at least 2x2",
+      height,
+      width
+    );
+    assert!(
+      top + height <= BUFFER_HEIGHT && left + width <= BUFFER_WIDTH,
+      "box of {}x{} at ({}, {}) exceeds the {}x{} screen",
+      height,
+      width,
+      top,
+      left,
+      BUFFER_HEIGHT,
+      BUFFER_WIDTH
+    );
+
+    let bottom = top + height - 1;
+    let right = left + width - 1;
+    let color_code = self.color_code;
+    let glyph = |ascii_character| ScreenChar {
+      ascii_character,
+      color_code,
+    };
+    let (horizontal, vertical) = (glyph(0xcd), glyph(0xba));
+
+    self.write_cell(top, left, glyph(0xc9));
+    self.write_cell(top, right, glyph(0xbb));
+    self.write_cell(bottom, left, glyph(0xc8));
+    self.write_cell(bottom, right, glyph(0xbc));
+    for col in left + 1..right {
+      self.write_cell(top, col, horizontal);
+      self.write_cell(bottom, col, horizontal);
+    }
+    for row in top + 1..bottom {
+      self.write_cell(row, left, vertical);
+      self.write_cell(row, right, vertical);
+    }
+
+    self.flush_if_unbuffered();
+  }
+
   /// Write a byte to the buffer
   fn write_byte(&mut self, byte: u8) {
     match byte {
@@ -122,35 +980,184 @@ impl Writer {
         self.new_line();
       }
 
-      byte => {
+      // backspace: erase the previous cell on the current row,
+      // never wrapping back to the previous line
+      0x08 => {
+        if self.column_position > 0 {
+          self.column_position -= 1;
+
+          let row = self.current_row();
+          let col = self.column_position;
+          let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+          };
+          self.write_cell(row, col, blank);
+        }
+      }
+
+      // tab: pad with blanks up to the next tab stop, or move to a new
+      // line if the tab stop lies beyond the end of the row
+      b'\t' => {
         if self.column_position >= BUFFER_WIDTH {
           self.new_line();
         }
 
-        let row = BUFFER_HEIGHT - 1;
-        let col = self.column_position;
-        let color_code = self.color_code;
+        let next_stop = (self.column_position / TAB_WIDTH + 1) * TAB_WIDTH;
+        if next_stop > BUFFER_WIDTH {
+          self.new_line();
+        } else {
+          let row = self.current_row();
+          let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+          };
+          for col in self.column_position..next_stop {
+            self.write_cell(row, col, blank);
+          }
+          self.column_position = next_stop;
+        }
+      }
 
-        self.buffer.chars[row][col].write(ScreenChar {
-          ascii_character: byte,
-          color_code,
-        });
+      byte => self.put_glyph(byte),
+    }
 
-        self.column_position += 1;
-      }
+    self.update_cursor();
+  }
+
+  /// Write a byte to the buffer as is, showing its code page 437 glyph.
+  /// Only `\n` is interpreted, every other byte, including control
+  /// characters like tab and backspace, is drawn.
+  fn write_raw_byte(&mut self, byte: u8) {
+    match byte {
+      b'\n' => self.new_line(),
+      byte => self.put_glyph(byte),
     }
+    self.update_cursor();
+  }
+
+  /// Draw `byte` at the current position and advance it, wrapping to a
+  /// new line at the end of the row.
+  fn put_glyph(&mut self, byte: u8) {
+    if self.column_position >= BUFFER_WIDTH {
+      self.new_line();
+    }
+
+    let row = self.current_row();
+    let col = self.column_position;
+    let color_code = self.color_code;
+
+    self.write_cell(
+      row,
+      col,
+      ScreenChar {
+        ascii_character: byte,
+        color_code,
+      },
+    );
+
+    self.column_position += 1;
   }
 
-  /// Move every row up and clear the last row for future use.
+  /// Move every row of the scroll region up and clear the last row
+  /// for future use.
   fn new_line(&mut self) {
-    for row in 1..BUFFER_HEIGHT {
-      for col in 0..BUFFER_WIDTH {
-        let c = self.buffer.chars[row][col].read();
-        self.buffer.chars[row - 1][col].write(c);
+    let (top, bottom) = (self.scroll_top, self.scroll_bottom);
+    if let Some(scrollback) = &mut self.scrollback {
+      scrollback.push(self.shadow[top]);
+    }
+    self.shadow.copy_within(top + 1..bottom, top);
+    for dirty in &mut self.dirty_rows[top..bottom] {
+      *dirty = true;
+    }
+    self.clear_row(self.current_row());
+    self.column_position = 0;
+    self.update_cursor();
+  }
+
+  /// Move the hardware cursor to the cell where the next character
+  /// will be written.
+  fn update_cursor(&mut self) {
+    let row = self.current_row();
+    // a full row wraps on the next write, keep the cursor on screen
+    // in the meantime
+    let col = self.column_position.min(BUFFER_WIDTH - 1);
+    let position = (row * BUFFER_WIDTH + col) as u16;
+
+    write_crtc(CRTC_CURSOR_LOCATION_LOW, position as u8);
+    write_crtc(CRTC_CURSOR_LOCATION_HIGH, (position >> 8) as u8);
+  }
+
+  /// Scroll the view of the scroll region back by `lines` rows of
+  /// history, stopping at the oldest row. Output written in the meantime
+  /// isn't shown until the view returns to the bottom.
+  fn scroll_up(&mut self, lines: usize) {
+    let history = match &self.scrollback {
+      Some(scrollback) => scrollback.len(),
+      None => return,
+    };
+    let view_offset = self.view_offset.saturating_add(lines).min(history);
+    if view_offset != self.view_offset {
+      self.view_offset = view_offset;
+      self.render_view();
+    }
+  }
+
+  /// Scroll the view of the scroll region forward by `lines` rows,
+  /// stopping at the live output.
+  fn scroll_down(&mut self, lines: usize) {
+    let view_offset = self.view_offset.saturating_sub(lines);
+    if view_offset != self.view_offset {
+      self.view_offset = view_offset;
+      self.render_view();
+    }
+  }
+
+  /// Show the live output in the scroll region again.
+  fn scroll_to_bottom(&mut self) {
+    self.scroll_down(self.view_offset);
+  }
+
+  /// Draw the scroll region as seen `view_offset` rows back: the newest
+  /// rows of the history followed by the top rows of the shadow buffer.
+  /// Without an offset, the shadow buffer is shown as usual.
+  fn render_view(&mut self) {
+    let (top, bottom) = (self.scroll_top, self.scroll_bottom);
+    let scrollback = match (&self.scrollback, self.view_offset) {
+      (Some(scrollback), view_offset) if view_offset > 0 => scrollback,
+      _ => {
+        for dirty in &mut self.dirty_rows[top..bottom] {
+          *dirty = true;
+        }
+        self.flush();
+        return;
+      }
+    };
+
+    let first = scrollback.len() - self.view_offset;
+    for (i, row) in (top..bottom).enumerate() {
+      let line = first + i;
+      let chars = if line < scrollback.len() {
+        scrollback.get(line)
+      } else {
+        &self.shadow[top + line - scrollback.len()]
+      };
+      for (col, c) in chars.iter().enumerate() {
+        self.buffer.chars[row][col].write(*c);
       }
     }
-    self.clear_row(BUFFER_HEIGHT - 1);
+  }
+
+  /// Blank every row of the scroll region and start writing at the
+  /// beginning of its last row again.
+  fn clear(&mut self) {
+    self.scroll_to_bottom();
+    for row in self.scroll_top..self.scroll_bottom {
+      self.clear_row(row);
+    }
     self.column_position = 0;
+    self.update_cursor();
+    self.flush_if_unbuffered();
   }
 
   /// Fill blank to all the cells in row
@@ -160,20 +1167,30 @@ impl Writer {
       color_code: self.color_code,
     };
     for col in 0..BUFFER_WIDTH {
-      self.buffer.chars[row][col].write(blank);
+      self.write_cell(row, col, blank);
     }
   }
 
-  /// Write a string to the buffer
+  /// Write raw bytes to the buffer, see [Writer::write_raw_byte].
+  fn write_raw(&mut self, bytes: &[u8]) {
+    for &byte in bytes {
+      self.write_raw_byte(byte);
+    }
+    self.flush_if_unbuffered();
+  }
+
+  /// Write a string to the buffer. Characters outside of printable ASCII
+  /// are transcoded to code page 437 where possible.
   fn write_string(&mut self, s: &str) {
-    for byte in s.bytes() {
-      match byte {
-        // printable ASCII byte or newline
-        0x20..=0x7e | b'\n' => self.write_byte(byte),
-        // not part of printable ASCII range
-        _ => self.write_byte(0xfe),
+    for c in s.chars() {
+      match c {
+        // printable ASCII character, newline, tab or backspace
+        ' '..='~' | '\n' | '\t' | '\x08' => self.write_byte(c as u8),
+        // not representable in code page 437
+        c => self.write_byte(char_to_cp437(c).unwrap_or(0xfe)),
       }
     }
+    self.flush_if_unbuffered();
   }
 }
 
@@ -240,4 +1257,390 @@ mod tests {
       }
     });
   }
+
+  #[test_case]
+  fn test_set_color() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+      let mut writer = WRITER.lock();
+      let old_color = writer.color_code;
+
+      writer.set_color(Color::LightGreen, Color::Blue);
+      writer.write_string("\nx\n");
+      let expected = ColorCode::new(Color::LightGreen, Color::Blue);
+      // the written character and the row cleared by scrolling
+      // both carry the new color
+      let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 2][0].read();
+      assert_eq!(screen_char.color_code, expected);
+      let blank = writer.buffer.chars[BUFFER_HEIGHT - 1][0].read();
+      assert_eq!(blank.color_code, expected);
+
+      writer.color_code = old_color;
+    });
+  }
+
+  #[test_case]
+  fn test_cprintln_restores_color() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+      let old_color = WRITER.lock().color_code;
+      cprintln!(Color::Red, Color::Black, "{}", "colored");
+
+      let expected = ColorCode::new(Color::Red, Color::Black);
+      let screen_char = WRITER.lock().buffer.chars[BUFFER_HEIGHT - 2][0].read();
+      assert_eq!(screen_char.ascii_character, b'c');
+      assert_eq!(screen_char.color_code, expected);
+      assert_eq!(WRITER.lock().color_code, old_color);
+    });
+  }
+
+  #[test_case]
+  fn test_backspace() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+      println!("\nab\x08c");
+      let writer = WRITER.lock();
+      let row = &writer.buffer.chars[BUFFER_HEIGHT - 2];
+      assert_eq!(row[0].read().ascii_character, b'a');
+      assert_eq!(row[1].read().ascii_character, b'c');
+      assert_eq!(row[2].read().ascii_character, b' ');
+    });
+  }
+
+  #[test_case]
+  fn test_backspace_at_line_start() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+      println!("\n\x08\x08a");
+      let writer = WRITER.lock();
+      let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 2][0].read();
+      assert_eq!(screen_char.ascii_character, b'a');
+    });
+  }
+
+  #[test_case]
+  fn test_tab() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+      println!("\na\tb");
+      let writer = WRITER.lock();
+      let row = &writer.buffer.chars[BUFFER_HEIGHT - 2];
+      assert_eq!(row[0].read().ascii_character, b'a');
+      for cell in &row[1..TAB_WIDTH] {
+        assert_eq!(cell.read().ascii_character, b' ');
+      }
+      assert_eq!(row[TAB_WIDTH].read().ascii_character, b'b');
+    });
+  }
+
+  #[test_case]
+  fn test_read_char() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+      cprintln!(Color::Cyan, Color::Magenta, "\nz");
+      assert_eq!(
+        read_char(BUFFER_HEIGHT - 2, 0),
+        (b'z', (Color::Cyan, Color::Magenta))
+      );
+    });
+  }
+
+  #[test_case]
+  fn test_scroll_region() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+      let mut writer = WRITER.lock();
+      writer.write_string("\npinned\n");
+      let pinned_row = BUFFER_HEIGHT - 2;
+
+      writer.set_scroll_region(pinned_row + 1, BUFFER_HEIGHT);
+      for _ in 0..5 {
+        writer.write_string("scrolled\n");
+      }
+      writer.set_scroll_region(0, BUFFER_HEIGHT);
+
+      let pinned = &writer.buffer.chars[pinned_row];
+      for (i, c) in "pinned".bytes().enumerate() {
+        assert_eq!(pinned[i].read().ascii_character, c);
+      }
+    });
+  }
+
+  #[test_case]
+  fn test_buffered_output() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+      let mut writer = WRITER.lock();
+      writer.write_string("\n");
+      writer.set_buffered(true);
+      writer.write_string("buffered");
+
+      // nothing reaches the screen before flushing
+      let row = BUFFER_HEIGHT - 1;
+      assert_eq!(writer.buffer.chars[row][0].read().ascii_character, b' ');
+      assert_eq!(writer.read_char(row, 0).0, b'b');
+
+      writer.set_buffered(false);
+      for (i, c) in "buffered".bytes().enumerate() {
+        assert_eq!(writer.buffer.chars[row][i].read().ascii_character, c);
+      }
+      writer.write_string("\n");
+    });
+  }
+
+  #[test_case]
+  fn test_cp437() {
+    assert_eq!(CP437_UPPER_HALF.chars().count(), 128);
+    assert_eq!(char_to_cp437('ü'), Some(0x81));
+    assert_eq!(char_to_cp437('é'), Some(0x82));
+    assert_eq!(char_to_cp437('ñ'), Some(0xa4));
+    assert_eq!(char_to_cp437('╔'), Some(0xc9));
+    assert_eq!(char_to_cp437('═'), Some(0xcd));
+    assert_eq!(char_to_cp437('║'), Some(0xba));
+    assert_eq!(char_to_cp437('█'), Some(0xdb));
+    assert_eq!(char_to_cp437('€'), None);
+  }
+
+  #[test_case]
+  fn test_println_utf8() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+      println!("\ncafé€");
+      let writer = WRITER.lock();
+      let row = &writer.buffer.chars[BUFFER_HEIGHT - 2];
+      assert_eq!(row[3].read().ascii_character, 0x82);
+      assert_eq!(row[4].read().ascii_character, 0xfe);
+    });
+  }
+
+  #[test_case]
+  fn test_write_raw() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+      println!();
+      write_raw(b"\xc9\x08\t\x01\n");
+      let writer = WRITER.lock();
+      let row = &writer.buffer.chars[BUFFER_HEIGHT - 2];
+      assert_eq!(row[0].read().ascii_character, 0xc9);
+      assert_eq!(row[1].read().ascii_character, 0x08);
+      assert_eq!(row[2].read().ascii_character, b'\t');
+      assert_eq!(row[3].read().ascii_character, 0x01);
+      assert_eq!(writer.column_position, 0);
+    });
+  }
+
+  #[test_case]
+  fn test_hexdump_format() {
+    use alloc::format;
+
+    static DATA: [u8; 20] = *b"Hello, world!\n\x00\x7f\xffabc";
+    assert_eq!(
+      format!("{}", HexDump(&DATA)),
+      "00000000: 4865 6c6c 6f2c 2077 6f72 6c64 210a 007f  Hello, world!...\n\
+       00000010: ff61 6263                                .abc\n"
+    );
+    assert_eq!(format!("{}", HexDump(&[])), "");
+    unsafe { hexdump(DATA.as_ptr(), DATA.len()) };
+  }
+
+  #[test_case]
+  fn test_clear_screen() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+      print!("\nnot for long");
+      clear_screen();
+      let writer = WRITER.lock();
+      let region =
+        &writer.buffer.chars[writer.scroll_top..writer.scroll_bottom];
+      for cell in region.iter().flatten() {
+        assert_eq!(cell.read().ascii_character, b' ');
+      }
+      assert_eq!(writer.column_position, 0);
+    });
+  }
+
+  #[test_case]
+  fn test_draw_box() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+      let mut writer = WRITER.lock();
+      let column_position = writer.column_position;
+      writer.draw_box(0, 0, 3, 3);
+
+      let chars = &writer.buffer.chars;
+      assert_eq!(chars[0][0].read().ascii_character, 0xc9);
+      assert_eq!(chars[0][1].read().ascii_character, 0xcd);
+      assert_eq!(chars[0][2].read().ascii_character, 0xbb);
+      assert_eq!(chars[1][0].read().ascii_character, 0xba);
+      assert_eq!(chars[1][2].read().ascii_character, 0xba);
+      assert_eq!(chars[2][0].read().ascii_character, 0xc8);
+      assert_eq!(chars[2][1].read().ascii_character, 0xcd);
+      assert_eq!(chars[2][2].read().ascii_character, 0xbc);
+      assert_eq!(writer.column_position, column_position);
+    });
+  }
+
+  #[test_case]
+  fn test_write_at() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+      let mut writer = WRITER.lock();
+      let column_position = writer.column_position;
+      writer.write_at(3, BUFFER_WIDTH - 2, "status");
+
+      let row = &writer.buffer.chars[3];
+      assert_eq!(row[BUFFER_WIDTH - 2].read().ascii_character, b's');
+      assert_eq!(row[BUFFER_WIDTH - 1].read().ascii_character, b't');
+      assert_eq!(writer.column_position, column_position);
+    });
+  }
+
+  #[test_case]
+  fn test_panel_writer_wraps() {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+      let mut writer = WRITER.lock();
+      let mut panel = PanelWriter {
+        writer: &mut writer,
+        row: 5,
+        col: 10,
+        left: 10,
+        right: 13,
+        bottom: 7,
+      };
+      write!(panel, "abcd\nefghij").unwrap();
+
+      assert_eq!(writer.read_char(5, 10).0, b'a');
+      assert_eq!(writer.read_char(5, 12).0, b'c');
+      assert_eq!(writer.read_char(6, 10).0, b'd');
+      // the explicit newline moves to row 7, which is cut off
+      assert_ne!(writer.read_char(7, 10).0, b'e');
+    });
+  }
+
+  #[test_case]
+  fn test_force_unlock() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+      // leak a guard as if its holder had panicked
+      core::mem::forget(WRITER.lock());
+      assert!(WRITER.try_lock().is_none());
+
+      unsafe { force_unlock() };
+      assert!(WRITER.try_lock().is_some());
+      println!("printing after force_unlock");
+    });
+  }
+
+  #[test_case]
+  fn test_status_bar() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+      let mut writer = WRITER.lock();
+      let column_position = writer.column_position;
+      writer.status_bar("uptime 1s");
+      assert_eq!(writer.column_position, column_position);
+
+      for _ in 0..BUFFER_HEIGHT {
+        writer.write_string("scrolling\n");
+      }
+
+      let row = &writer.buffer.chars[0];
+      for (i, c) in "uptime 1s".bytes().enumerate() {
+        assert_eq!(row[i].read().ascii_character, c);
+        assert_eq!(row[i].read().color_code, STATUS_BAR_COLOR);
+      }
+      // padded to the full width
+      let last = row[BUFFER_WIDTH - 1].read();
+      assert_eq!(last.ascii_character, b' ');
+      assert_eq!(last.color_code, STATUS_BAR_COLOR);
+
+      writer.set_scroll_region(0, BUFFER_HEIGHT);
+    });
+  }
+
+  #[test_case]
+  fn test_scrollback_ring() {
+    let mut scrollback = Scrollback::new(4).unwrap();
+    for i in 0..6 {
+      let mut row = [ScreenChar {
+        ascii_character: b' ',
+        color_code: ColorCode(0),
+      }; BUFFER_WIDTH];
+      row[0].ascii_character = b'0' + i;
+      scrollback.push(row);
+    }
+    // the two oldest rows were dropped
+    assert_eq!(scrollback.len(), 4);
+    assert_eq!(scrollback.get(0)[0].ascii_character, b'2');
+    assert_eq!(scrollback.get(3)[0].ascii_character, b'5');
+  }
+
+  #[test_case]
+  fn test_scroll_up_and_down() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+      let mut writer = WRITER.lock();
+      let old_scrollback =
+        writer.scrollback.replace(Scrollback::new(4).unwrap());
+
+      // push "history" just past the top of the screen
+      writer.write_string("history\n");
+      for _ in 0..BUFFER_HEIGHT - 1 {
+        writer.write_string("\n");
+      }
+      writer.scroll_up(1);
+      for (i, c) in "history".bytes().enumerate() {
+        assert_eq!(writer.buffer.chars[0][i].read().ascii_character, c);
+      }
+      // scrolling stops at the oldest row
+      writer.scroll_up(100);
+      assert_eq!(writer.view_offset, 4);
+
+      // output is held back until returning to the bottom
+      writer.write_string("live");
+      let row = BUFFER_HEIGHT - 1;
+      assert_ne!(writer.buffer.chars[row][0].read().ascii_character, b'l');
+      writer.scroll_down(2);
+      assert_eq!(writer.view_offset, 2);
+      writer.scroll_to_bottom();
+      assert_eq!(writer.view_offset, 0);
+      for (i, c) in "live".bytes().enumerate() {
+        assert_eq!(writer.buffer.chars[row][i].read().ascii_character, c);
+      }
+
+      writer.write_string("\n");
+      writer.scrollback = old_scrollback;
+    });
+  }
+
+  #[test_case]
+  fn test_try_print_doesnt_block() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+      let writer = WRITER.lock();
+      assert!(!try_print(format_args!("dropped")));
+      drop(writer);
+      assert!(try_print(format_args!("printed\n")));
+    });
+  }
 }
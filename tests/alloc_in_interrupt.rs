@@ -0,0 +1,91 @@
+//! Tests that interrupt handlers can allocate without deadlocking on the
+//! allocator lock, both from an exception handler and from a timer
+//! interrupt arriving while normal code allocates.
+
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, vec::Vec};
+use blog_os::{
+  allocator,
+  interrupts::{InterruptIndex, PICS},
+  memory::{self, BootInfoFrameAllocator},
+  serial_print, serial_println,
+  test_harness::{exit_qemu, QemuExitCode},
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use lazy_static::lazy_static;
+use x86_64::{
+  structures::idt::{InterruptDescriptorTable, InterruptStackFrame},
+  VirtAddr,
+};
+
+/// Number of allocations made by the timer handler
+static TIMER_ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+  static ref TEST_IDT: InterruptDescriptorTable = {
+    let mut idt = InterruptDescriptorTable::new();
+    idt.breakpoint.set_handler_fn(test_breakpoint_handler);
+    idt[InterruptIndex::Timer as usize]
+      .set_handler_fn(test_timer_interrupt_handler);
+    idt
+  };
+}
+
+extern "x86-interrupt" fn test_breakpoint_handler(_frame: InterruptStackFrame) {
+  let value = Box::new(41);
+  assert_eq!(*value + 1, 42);
+}
+
+extern "x86-interrupt" fn test_timer_interrupt_handler(
+  _frame: InterruptStackFrame,
+) {
+  let value = Box::new(TIMER_ALLOCATIONS.load(Ordering::SeqCst));
+  TIMER_ALLOCATIONS.store(*value + 1, Ordering::SeqCst);
+  unsafe {
+    PICS
+      .lock()
+      .notify_end_of_interrupt(InterruptIndex::Timer as u8);
+  }
+}
+
+entry_point!(test_kernel_entry);
+
+fn test_kernel_entry(boot_info: &'static BootInfo) -> ! {
+  serial_print!("alloc_in_interrupt::alloc_in_interrupt...\t");
+
+  let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+  let mut mapper = unsafe { memory::init_offset_page_table(phys_mem_offset) };
+  let mut frame_allocator =
+    unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+  allocator::init_heap_default(&mut mapper, &mut frame_allocator)
+    .expect("heap initialization failed");
+
+  TEST_IDT.load();
+  x86_64::instructions::interrupts::int3();
+
+  unsafe { PICS.lock().initialize() };
+  x86_64::instructions::interrupts::enable();
+
+  // keep allocating until the timer has interrupted us a few times,
+  // most likely while the allocator was locked at least once
+  while TIMER_ALLOCATIONS.load(Ordering::SeqCst) < 10 {
+    let values: Vec<u64> = (0..64).collect();
+    assert_eq!(values.iter().sum::<u64>(), 63 * 64 / 2);
+  }
+  x86_64::instructions::interrupts::disable();
+
+  serial_println!("[ok]");
+  exit_qemu(QemuExitCode::Success);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+  blog_os::test_harness::test_panic_handler(info)
+}
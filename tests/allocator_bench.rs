@@ -0,0 +1,128 @@
+//! Benchmarks of the heap allocator. Run with each of the allocator
+//! features to compare them under the same workload.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_harness::bench_runner)]
+#![reexport_test_harness_main = "bench_main"]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, vec::Vec};
+use blog_os::test_harness::{exit_qemu, QemuExitCode};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+  use blog_os::allocator;
+  use blog_os::memory::{self, BootInfoFrameAllocator};
+  use x86_64::VirtAddr;
+
+  blog_os::init();
+  let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+  let mut mapper = unsafe { memory::init_offset_page_table(phys_mem_offset) };
+  let mut frame_allocator =
+    unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+  allocator::init_heap_default(&mut mapper, &mut frame_allocator)
+    .expect("heap initialization failed");
+
+  bench_main();
+
+  exit_qemu(QemuExitCode::Success);
+}
+
+const ROUNDS: usize = 100;
+
+#[test_case]
+fn short_lived_boxes() {
+  for _ in 0..ROUNDS {
+    for i in 0..1000 {
+      let x = Box::new(i);
+      volatile::Volatile::new(*x).read();
+    }
+  }
+}
+
+#[test_case]
+fn growing_vec() {
+  for _ in 0..ROUNDS {
+    let mut vec = Vec::new();
+    for i in 0..1000 {
+      vec.push(i);
+    }
+    volatile::Volatile::new(vec.len()).read();
+  }
+}
+
+#[test_case]
+fn mixed_sizes() {
+  for _ in 0..ROUNDS {
+    let small: Vec<Box<u64>> = (0..64).map(Box::new).collect();
+    let large: Vec<Box<[u8; 1024]>> =
+      (0..16).map(|_| Box::new([0; 1024])).collect();
+    volatile::Volatile::new(small.len() + large.len()).read();
+  }
+}
+
+/// Number of allocations alive at once in [interrupts_off_window]
+const LIVE_ALLOCATIONS: usize = 64;
+
+/// Measure how long single `alloc` and `dealloc` calls keep interrupts
+/// disabled, i.e., hold the allocator's lock, and print the worst case
+#[test_case]
+fn interrupts_off_window() {
+  use alloc::alloc::{alloc, dealloc, Layout};
+  use blog_os::cpu::{cycles_to_ns, rdtsc};
+  use blog_os::{serial_print, serial_println};
+  use x86_64::instructions::interrupts;
+
+  // an interrupt in the middle of a call would be counted as well
+  fn timed<T>(f: impl FnOnce() -> T) -> (T, u64) {
+    interrupts::without_interrupts(|| {
+      let start = rdtsc();
+      let result = f();
+      (result, rdtsc() - start)
+    })
+  }
+
+  let layouts = [
+    Layout::new::<u64>(),
+    Layout::new::<[u8; 256]>(),
+    Layout::new::<[u8; 1024]>(),
+  ];
+  let mut live = [(core::ptr::null_mut(), layouts[0]); LIVE_ALLOCATIONS];
+  let (mut alloc_max, mut dealloc_max, mut calls) = (0, 0, 0);
+  for _ in 0..ROUNDS {
+    for (i, slot) in live.iter_mut().enumerate() {
+      let layout = layouts[i % layouts.len()];
+      let (ptr, cycles) = timed(|| unsafe { alloc(layout) });
+      assert!(!ptr.is_null());
+      *slot = (ptr, layout);
+      alloc_max = alloc_max.max(cycles);
+    }
+    // free every other allocation first, to fragment the heap
+    for &(ptr, layout) in
+      live.iter().step_by(2).chain(live.iter().skip(1).step_by(2))
+    {
+      let ((), cycles) = timed(|| unsafe { dealloc(ptr, layout) });
+      dealloc_max = dealloc_max.max(cycles);
+    }
+    calls += LIVE_ALLOCATIONS;
+  }
+
+  for (name, cycles) in [("alloc", alloc_max), ("dealloc", dealloc_max)] {
+    serial_print!("  longest {} of {}: {} cycles", name, calls, cycles);
+    if let Some(ns) = cycles_to_ns(cycles) {
+      serial_print!(" (~{} ns)", ns);
+    }
+    serial_println!();
+  }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+  blog_os::test_harness::test_panic_handler(info)
+}
@@ -0,0 +1,58 @@
+//! Test that the tick counter keeps advancing once the local APIC timer
+//! has taken over the timer interrupt from the PIT.
+
+#![no_std]
+#![no_main]
+
+use blog_os::{
+  apic,
+  interrupts::{self, PICS},
+  memory::{self, BootInfoFrameAllocator},
+  serial_print, serial_println,
+  test_harness::{exit_qemu, should_panic, QemuExitCode},
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+
+entry_point!(test_kernel_entry);
+
+fn test_kernel_entry(boot_info: &'static BootInfo) -> ! {
+  serial_print!("apic_timer::apic_timer...\t");
+  blog_os::init();
+
+  let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+  let mut mapper = unsafe { memory::init_offset_page_table(phys_mem_offset) };
+  let mut frame_allocator =
+    unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+  // QEMU always emulates a local APIC
+  apic::init(&mut mapper, &mut frame_allocator).expect("apic::init failed");
+  assert!(apic::timer_enabled());
+  // a second call leaves the running timer alone
+  apic::init(&mut mapper, &mut frame_allocator).expect("apic::init failed");
+
+  // the PIT no longer interrupts, but the keyboard still does
+  let [primary, _] = unsafe { PICS.lock().read_masks() };
+  assert_eq!(primary & 0b11, 0b01);
+
+  // this hangs until the test times out unless the APIC timer ticks
+  let start = interrupts::ticks();
+  interrupts::sleep_ticks(5);
+  assert!(interrupts::ticks() - start >= 5);
+
+  // the PIT can't change the frequency the ticks are converted with anymore
+  should_panic(|| interrupts::init_pit(50), "can't be reprogrammed");
+  assert_eq!(
+    interrupts::timer_frequency(),
+    interrupts::TIMER_FREQUENCY_HZ
+  );
+
+  serial_println!("[ok]");
+  exit_qemu(QemuExitCode::Success);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+  blog_os::test_harness::test_panic_handler(info)
+}
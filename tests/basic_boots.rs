@@ -8,8 +8,8 @@
 
 use core::panic::PanicInfo;
 
-use blog_os::println;
 use blog_os::test_harness::{exit_qemu, test_panic_handler, QemuExitCode};
+use blog_os::{kassert, kassert_eq, println};
 
 #[no_mangle] // don't mangle the name of this function
 pub extern "C" fn _start() -> ! {
@@ -26,3 +26,9 @@ fn panic(info: &PanicInfo) -> ! {
 fn test_println() {
   println!("test_println output");
 }
+
+#[test_case]
+fn test_kassert() {
+  kassert!(1 + 1 == 2);
+  kassert_eq!(1 + 1, 2);
+}
@@ -0,0 +1,96 @@
+//! Tests that overflowing the double fault stack set up by
+//! [blog_os::gdt::init_gdt_with_guard] hits its guard page and raises a
+//! page fault, instead of silently corrupting adjacent memory.
+
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use blog_os::{
+  gdt::{self, DOUBLE_FAULT_IST_INDEX, PAGE_FAULT_IST_INDEX, STACK_SIZE},
+  memory::{self, BootInfoFrameAllocator},
+  serial_print, serial_println,
+  test_harness::{exit_qemu, QemuExitCode},
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use lazy_static::lazy_static;
+use x86_64::{
+  registers::control::Cr2,
+  structures::idt::{
+    InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode,
+  },
+  VirtAddr,
+};
+
+lazy_static! {
+  static ref TEST_IDT: InterruptDescriptorTable = {
+    let mut idt = InterruptDescriptorTable::new();
+    unsafe {
+      idt
+        .double_fault
+        .set_handler_fn(test_double_fault_handler)
+        .set_stack_index(DOUBLE_FAULT_IST_INDEX);
+      idt
+        .page_fault
+        .set_handler_fn(test_page_fault_handler)
+        .set_stack_index(PAGE_FAULT_IST_INDEX);
+    }
+    idt
+  };
+}
+
+extern "x86-interrupt" fn test_double_fault_handler(
+  _frame: InterruptStackFrame,
+  _err_code: u64,
+) -> ! {
+  stack_overflow();
+  panic!("Execution continued after stack overflow");
+}
+
+extern "x86-interrupt" fn test_page_fault_handler(
+  _frame: InterruptStackFrame,
+  _err_code: PageFaultErrorCode,
+) {
+  let stack_bottom =
+    gdt::interrupt_stack_top(DOUBLE_FAULT_IST_INDEX) - STACK_SIZE;
+  let guard_page = (stack_bottom - 4096u64)..stack_bottom;
+  let addr = Cr2::read();
+  assert!(
+    guard_page.contains(&addr),
+    "fault outside guard page: {:?}",
+    addr
+  );
+
+  serial_println!("[ok]");
+  exit_qemu(QemuExitCode::Success);
+}
+
+entry_point!(test_kernel_entry);
+
+fn test_kernel_entry(boot_info: &'static BootInfo) -> ! {
+  serial_print!("double_fault_stack_guard::double_fault_stack_guard...\t");
+
+  let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+  let mut mapper = unsafe { memory::init_offset_page_table(phys_mem_offset) };
+  let mut frame_allocator =
+    unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+  gdt::init_gdt_with_guard(&mut mapper, &mut frame_allocator)
+    .expect("mapping interrupt stacks failed");
+  TEST_IDT.load();
+
+  // enter the double fault handler on its own stack
+  unsafe { core::arch::asm!("int 8") };
+  panic!("Execution continued after double fault");
+}
+
+#[allow(unconditional_recursion)]
+fn stack_overflow() {
+  stack_overflow();
+  volatile::Volatile::new(0).read(); // prevent tail recursion optimizations
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+  blog_os::test_harness::test_panic_handler(info)
+}
@@ -0,0 +1,69 @@
+//! Test that frames returned to the [BootInfoFrameAllocator] are
+//! handed out again.
+
+#![no_std]
+#![no_main]
+
+use blog_os::{
+  allocator,
+  memory::{self, BootInfoFrameAllocator},
+  serial_print, serial_println,
+  test_harness::{exit_qemu, QemuExitCode},
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::{
+  structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags,
+  },
+  VirtAddr,
+};
+
+entry_point!(test_kernel_entry);
+
+fn test_kernel_entry(boot_info: &'static BootInfo) -> ! {
+  serial_print!("frame_deallocator::frame_deallocator...\t");
+
+  let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+  let mut mapper = unsafe { memory::init_offset_page_table(phys_mem_offset) };
+  let mut frame_allocator =
+    unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+  // the free list lives on the heap
+  allocator::init_heap_default(&mut mapper, &mut frame_allocator)
+    .expect("heap initialization failed");
+
+  let first = frame_allocator.allocate_frame().expect("out of frames");
+  let second = frame_allocator.allocate_frame().expect("out of frames");
+  assert_ne!(first, second);
+  unsafe { frame_allocator.deallocate_frame(first) };
+  assert_eq!(frame_allocator.allocate_frame(), Some(first));
+
+  // mapping and unmapping the same page over and over keeps reusing the
+  // same frame instead of consuming fresh ones
+  let page = Page::containing_address(VirtAddr::new(0x_5555_6666_0000));
+  let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+  let mut mapped_frame = None;
+  let mut free_frames = None;
+  for _ in 0..1000 {
+    memory::map_range(page, 1, flags, &mut mapper, &mut frame_allocator)
+      .expect("mapping failed");
+    let frame = mapper.translate_page(page).expect("page not mapped");
+    unsafe {
+      memory::unmap_and_free_page(page, &mut mapper, &mut frame_allocator)
+        .expect("unmapping failed");
+    }
+
+    // the first round also allocates the page tables
+    assert_eq!(*mapped_frame.get_or_insert(frame), frame);
+    let free = *free_frames.get_or_insert(frame_allocator.free_frames());
+    assert_eq!(frame_allocator.free_frames(), free);
+  }
+
+  serial_println!("[ok]");
+  exit_qemu(QemuExitCode::Success);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+  blog_os::test_harness::test_panic_handler(info)
+}
@@ -0,0 +1,59 @@
+//! Tests that loading an invalid segment raises a general protection
+//! fault that is delivered to its handler instead of escalating into
+//! a double or triple fault.
+
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use blog_os::{
+  serial_print, serial_println, test_harness::exit_qemu,
+  test_harness::QemuExitCode,
+};
+use core::panic::PanicInfo;
+use lazy_static::lazy_static;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+/// A selector far beyond the end of the GDT
+const BAD_SELECTOR: u16 = 0xfff8;
+
+lazy_static! {
+  static ref TEST_IDT: InterruptDescriptorTable = {
+    let mut idt = InterruptDescriptorTable::new();
+    idt
+      .general_protection_fault
+      .set_handler_fn(test_general_protection_fault_handler);
+    idt
+  };
+}
+
+extern "x86-interrupt" fn test_general_protection_fault_handler(
+  _frame: InterruptStackFrame,
+  error_code: u64,
+) {
+  // the error code holds the offending selector without its RPL bits
+  assert_eq!(error_code, u64::from(BAD_SELECTOR));
+  serial_println!("[ok]");
+  exit_qemu(QemuExitCode::Success);
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+  use x86_64::instructions::segmentation::{Segment, DS};
+  use x86_64::structures::gdt::SegmentSelector;
+
+  serial_print!("general_protection_fault::general_protection_fault...\t");
+
+  blog_os::gdt::init_gdt();
+  TEST_IDT.load();
+
+  unsafe {
+    DS::set_reg(SegmentSelector(BAD_SELECTOR));
+  }
+  panic!("Execution continued after loading an invalid segment");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+  blog_os::test_harness::test_panic_handler(info)
+}
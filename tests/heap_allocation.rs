@@ -22,7 +22,7 @@ fn main(boot_info: &'static BootInfo) -> ! {
   let mut mapper = unsafe { memory::init_offset_page_table(phys_mem_offset) };
   let mut frame_allocator =
     unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
-  allocator::init_heap(&mut mapper, &mut frame_allocator)
+  allocator::init_heap_default(&mut mapper, &mut frame_allocator)
     .expect("heap initialization failed");
 
   test_main();
@@ -76,6 +76,65 @@ fn many_boxes_long_lived() {
   assert_eq!(*long_lived, 1); // new
 }
 
+#[test_case]
+fn zeroed_vec_after_reuse() {
+  use alloc::vec;
+
+  const N: usize = 512;
+  // dirty a region and give it back to the allocator
+  drop(vec![0xffu8; N]);
+
+  // allocated through alloc_zeroed, likely in the same spot
+  let zeroed = vec![0u8; N];
+  assert!(zeroed.iter().all(|&b| b == 0));
+}
+
+#[cfg(not(feature = "fixed"))]
+#[test_case]
+fn stats_track_allocations() {
+  use alloc::boxed::Box;
+  use blog_os::allocator;
+
+  let before = allocator::stats().allocated_bytes;
+  let x = Box::new([0u64; 16]);
+  let during = allocator::stats().allocated_bytes;
+  assert!(during > before);
+  drop(x);
+  assert!(allocator::stats().allocated_bytes < during);
+}
+
+#[cfg(not(feature = "fixed"))]
+#[test_case]
+fn stats_display_when_exhausted() {
+  use alloc::alloc::{alloc, dealloc, Layout};
+  use blog_os::{allocator, println};
+  use core::ptr;
+
+  // chain the blocks through their first word, so that keeping track of
+  // them doesn't need any allocation of its own
+  let layout = Layout::new::<[usize; 128]>();
+  let mut head: *mut u8 = ptr::null_mut();
+  loop {
+    let block = unsafe { alloc(layout) };
+    if block.is_null() {
+      break;
+    }
+    unsafe { (block as *mut *mut u8).write(head) };
+    head = block;
+  }
+
+  let stats = allocator::stats();
+  assert!(stats.largest_free_block < layout.size());
+  // this would end up in the out-of-memory handler if it allocated
+  println!("exhausted heap: {}", stats);
+
+  while !head.is_null() {
+    let next = unsafe { (head as *mut *mut u8).read() };
+    unsafe { dealloc(head, layout) };
+    head = next;
+  }
+}
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
   blog_os::test_harness::test_panic_handler(info)
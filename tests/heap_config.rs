@@ -0,0 +1,58 @@
+//! Test that the heap can be set up at a custom location and size, and
+//! that a location clashing with existing mappings is rejected.
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec;
+use blog_os::{
+  allocator::{self, HeapConfig, HEAP_SIZE},
+  memory::{self, BootInfoFrameAllocator},
+  serial_print, serial_println,
+  test_harness::{exit_qemu, QemuExitCode},
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+
+entry_point!(test_kernel_entry);
+
+fn test_kernel_entry(boot_info: &'static BootInfo) -> ! {
+  serial_print!("heap_config::heap_config...\t");
+
+  let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+  let mut mapper = unsafe { memory::init_offset_page_table(phys_mem_offset) };
+  let mut frame_allocator =
+    unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+  // the physical memory is mapped already
+  let clashing = HeapConfig {
+    start: phys_mem_offset.as_u64() as usize,
+    size: HEAP_SIZE,
+    ..HeapConfig::default()
+  };
+  assert!(
+    allocator::init_heap(clashing, &mut mapper, &mut frame_allocator).is_err()
+  );
+
+  // ten times the default, so the allocation below wouldn't fit otherwise
+  let config = HeapConfig {
+    start: 0x_4444_8888_0000,
+    size: 10 * HEAP_SIZE,
+    ..HeapConfig::default()
+  };
+  allocator::init_heap(config, &mut mapper, &mut frame_allocator)
+    .expect("heap initialization failed");
+  let big = vec![1u8; 5 * HEAP_SIZE];
+  assert_eq!(big.iter().map(|&b| b as usize).sum::<usize>(), big.len());
+
+  serial_println!("[ok]");
+  exit_qemu(QemuExitCode::Success);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+  blog_os::test_harness::test_panic_handler(info)
+}
@@ -0,0 +1,71 @@
+//! Tests that reading past the end of the heap hits the guard page set
+//! up by [blog_os::allocator::init_heap] and raises a page fault, instead
+//! of returning whatever follows the heap.
+
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use blog_os::{
+  allocator::{self, HEAP_SIZE, HEAP_START},
+  memory::{self, BootInfoFrameAllocator},
+  serial_print, serial_println,
+  test_harness::{exit_qemu, QemuExitCode},
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use lazy_static::lazy_static;
+use x86_64::{
+  registers::control::Cr2,
+  structures::idt::{
+    InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode,
+  },
+  VirtAddr,
+};
+
+lazy_static! {
+  static ref TEST_IDT: InterruptDescriptorTable = {
+    let mut idt = InterruptDescriptorTable::new();
+    idt.page_fault.set_handler_fn(test_page_fault_handler);
+    idt
+  };
+}
+
+/// The first address past the end of the heap
+const HEAP_END: u64 = (HEAP_START + HEAP_SIZE) as u64;
+
+extern "x86-interrupt" fn test_page_fault_handler(
+  _frame: InterruptStackFrame,
+  _err_code: PageFaultErrorCode,
+) {
+  assert_eq!(Cr2::read(), VirtAddr::new(HEAP_END));
+  serial_println!("[ok]");
+  exit_qemu(QemuExitCode::Success);
+}
+
+entry_point!(test_kernel_entry);
+
+fn test_kernel_entry(boot_info: &'static BootInfo) -> ! {
+  serial_print!("heap_guard_page::heap_guard_page...\t");
+
+  let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+  let mut mapper = unsafe { memory::init_offset_page_table(phys_mem_offset) };
+  let mut frame_allocator =
+    unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+  allocator::init_heap_default(&mut mapper, &mut frame_allocator)
+    .expect("heap initialization failed");
+  TEST_IDT.load();
+
+  // the last byte of the heap is fine, the next one is in the guard page
+  let last = (HEAP_END - 1) as *const u8;
+  unsafe { last.read_volatile() };
+  let past_end = HEAP_END as *const u8;
+  let value = unsafe { past_end.read_volatile() };
+
+  panic!("read {:#x} past the end of the heap", value);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+  blog_os::test_harness::test_panic_handler(info)
+}
@@ -0,0 +1,43 @@
+//! Test that the kernel's boot messages can be read back from the
+//! in-memory log.
+
+#![no_std]
+#![no_main]
+
+use blog_os::{
+  allocator, log,
+  memory::{self, BootInfoFrameAllocator},
+  serial_print, serial_println,
+  test_harness::{exit_qemu, QemuExitCode},
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+
+entry_point!(test_kernel_entry);
+
+fn test_kernel_entry(boot_info: &'static BootInfo) -> ! {
+  serial_print!("log_buffer::heap_initialized_logged...\t");
+
+  blog_os::init();
+  // the heap's location is logged as a detail
+  log::set_log_level(log::LogLevel::Debug);
+  let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+  let mut mapper = unsafe { memory::init_offset_page_table(phys_mem_offset) };
+  let mut frame_allocator =
+    unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+  allocator::init_heap_default(&mut mapper, &mut frame_allocator)
+    .expect("heap initialization failed");
+
+  let dump = log::dump();
+  assert!(dump.lines().any(|line| line.contains("heap initialized")));
+  drop(dump);
+
+  serial_println!("[ok]");
+  exit_qemu(QemuExitCode::Success);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+  blog_os::test_harness::test_panic_handler(info)
+}
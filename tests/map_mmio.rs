@@ -0,0 +1,56 @@
+//! Test that device memory mapped through [memory::map_mmio] is
+//! uncacheable.
+
+#![no_std]
+#![no_main]
+
+use blog_os::{
+  memory::{self, BootInfoFrameAllocator, Translation},
+  serial_print, serial_println,
+  test_harness::{exit_qemu, QemuExitCode},
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::{
+  structures::paging::{Page, PageTableFlags, PhysFrame},
+  PhysAddr, VirtAddr,
+};
+
+entry_point!(test_kernel_entry);
+
+fn test_kernel_entry(boot_info: &'static BootInfo) -> ! {
+  serial_print!("map_mmio::map_mmio...\t");
+
+  let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+  let mut mapper = unsafe { memory::init_offset_page_table(phys_mem_offset) };
+  let mut frame_allocator =
+    unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+  // map the VGA buffer a second time
+  let addr = VirtAddr::new(0x_5555_7777_0000);
+  let page = Page::containing_address(addr);
+  let frame = PhysFrame::containing_address(PhysAddr::new(0xb8000));
+  unsafe {
+    memory::map_mmio(page, frame, &mut mapper, &mut frame_allocator)
+      .expect("map_mmio failed");
+  }
+
+  let translation =
+    unsafe { memory::translate_with_flags(addr, phys_mem_offset) };
+  match translation {
+    Some(Translation::Mapped(phys, flags)) => {
+      assert_eq!(phys, frame.start_address());
+      assert!(flags.contains(PageTableFlags::NO_CACHE));
+      assert!(flags.contains(PageTableFlags::WRITE_THROUGH));
+    }
+    other => panic!("unexpected translation {:?}", other),
+  }
+
+  serial_println!("[ok]");
+  exit_qemu(QemuExitCode::Success);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+  blog_os::test_harness::test_panic_handler(info)
+}
@@ -0,0 +1,37 @@
+//! Tests that a failed heap allocation runs the registered
+//! out-of-memory handler before panicking.
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::{alloc::Layout, boxed::Box};
+use blog_os::{
+  serial_print, serial_println, test_harness::exit_qemu,
+  test_harness::QemuExitCode,
+};
+use core::panic::PanicInfo;
+
+fn test_oom_handler(layout: Layout) {
+  assert_eq!(layout.size(), 1024);
+  serial_println!("[ok]");
+  exit_qemu(QemuExitCode::Success);
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+  serial_print!("oom_handler::oom_handler...\t");
+
+  blog_os::allocator::set_oom_handler(test_oom_handler);
+
+  // the heap is never initialized, so every allocation fails
+  let value = Box::new([0u8; 1024]);
+  volatile::Volatile::new(value[0]).read();
+  panic!("Execution continued after failed allocation");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+  blog_os::test_harness::test_panic_handler(info)
+}
@@ -5,23 +5,67 @@
 #![no_main]
 
 use blog_os::{
-  memory::active_level4_page_table,
+  memory::{self, active_level4_page_table, translate_virt_address},
   println, serial_print, serial_println,
   test_harness::{exit_qemu, QemuExitCode},
 };
-use bootloader::{entry_point, BootInfo};
+use bootloader::{bootinfo::MemoryRegionType, entry_point, BootInfo};
 use core::panic::PanicInfo;
-use x86_64::VirtAddr;
+use x86_64::{
+  structures::paging::{mapper::MappedFrame, PageTableFlags},
+  PhysAddr, VirtAddr,
+};
 
 entry_point!(test_kernel_entry);
 
 fn test_kernel_entry(boot_info: &'static BootInfo) -> ! {
   serial_print!("page_table_access::page_table_access...\t");
-  let table = unsafe {
-    active_level4_page_table(VirtAddr::new(boot_info.physical_memory_offset))
-  };
+  let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+  let table = unsafe { active_level4_page_table(phys_mem_offset) };
   // we don't want to see the table, just make sure it doesn't crash
   println!("{:?}", table);
+
+  // the bootloader maps physical memory with 2MiB pages
+  let phys = unsafe {
+    translate_virt_address(phys_mem_offset + 0x20_1234u64, phys_mem_offset)
+  };
+  assert_eq!(phys, Some(PhysAddr::new(0x20_1234)));
+
+  // the hand-written walker agrees with the mapper
+  let mapper = unsafe { memory::init_offset_page_table(phys_mem_offset) };
+  let addresses = [
+    phys_mem_offset,
+    phys_mem_offset + 0xb8000u64,
+    phys_mem_offset + 0x20_1234u64,
+    VirtAddr::from_ptr(&phys),
+    VirtAddr::new(0x_5555_9999_0000),
+  ];
+  for addr in addresses {
+    let expected = memory::translate(addr, &mapper);
+    let walked = unsafe { translate_virt_address(addr, phys_mem_offset) };
+    assert_eq!(walked, expected, "translating {:?}", addr);
+  }
+  // every mapping found by walking the tables translates as expected, and
+  // there are both regular pages and the 2MiB pages mapping physical memory
+  let (mut small, mut large) = (0, 0);
+  for (page, frame, flags) in memory::iter_mappings(&mapper) {
+    assert!(flags.contains(PageTableFlags::PRESENT));
+    let expected = memory::translate(page.start_address(), &mapper);
+    assert_eq!(Some(frame.start_address()), expected, "mapping {:?}", page);
+    match frame {
+      MappedFrame::Size4KiB(_) => small += 1,
+      MappedFrame::Size2MiB(_) => large += 1,
+      MappedFrame::Size1GiB(_) => {}
+    }
+  }
+  assert!(small > 0 && large > 0);
+
+  // QEMU gives us some usable RAM, which the report accounts for
+  let report = memory::memory_report(&boot_info.memory_map);
+  let usable = report.bytes(MemoryRegionType::Usable);
+  assert!(usable > 0 && usable <= report.total_bytes());
+  println!("{}", report);
+
   serial_println!("[ok]");
   exit_qemu(QemuExitCode::Success);
 }
@@ -0,0 +1,34 @@
+//! Test that a panic hook installed through the test harness turns an
+//! expected panic into a passing test.
+
+#![no_std]
+#![no_main]
+
+use blog_os::{
+  serial_print, serial_println,
+  test_harness::{self, exit_qemu, PanicMessage, QemuExitCode},
+};
+use core::panic::PanicInfo;
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+  serial_print!("panic_hook::expected_panic...\t");
+
+  test_harness::set_panic_hook(check_message);
+  panic!("expected failure {}", 42);
+}
+
+fn check_message(message: &PanicMessage) -> ! {
+  if message.as_str().contains("expected failure 42") {
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+  }
+  serial_println!("[failed]\n");
+  serial_println!("Error: unexpected panic: {}\n", message);
+  exit_qemu(QemuExitCode::Failed);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+  test_harness::test_panic_handler(info)
+}
@@ -0,0 +1,73 @@
+//! Test that walking the page tables through a recursive mapping finds
+//! the same physical addresses as walking them through the physical
+//! memory offset.
+
+#![no_std]
+#![no_main]
+
+use blog_os::{
+  memory::{
+    active_level4_page_table, translate_recursive, translate_virt_address,
+  },
+  serial_print, serial_println,
+  test_harness::{exit_qemu, QemuExitCode},
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::{
+  instructions::tlb, registers::control::Cr3,
+  structures::paging::PageTableFlags, VirtAddr,
+};
+
+entry_point!(test_kernel_entry);
+
+/// Lives in the kernel image
+static KERNEL_DATA: u64 = 0;
+
+fn test_kernel_entry(boot_info: &'static BootInfo) -> ! {
+  serial_print!("recursive_translation::recursive_translation...\t");
+
+  let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+  let level4_table = unsafe { active_level4_page_table(phys_mem_offset) };
+
+  // the bootloader doesn't set up a recursive mapping, so point an
+  // unused level 4 entry back to the table
+  let recursive_index = (0..512)
+    .rev()
+    .find(|&i| level4_table[i].is_unused())
+    .expect("no unused level 4 entry");
+  let (level4_frame, _) = Cr3::read();
+  level4_table[recursive_index].set_frame(
+    level4_frame,
+    PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+  );
+  tlb::flush_all();
+
+  let stack_var = 0u64;
+  let addresses = [
+    // the VGA buffer, through the 2MiB pages of the physical memory mapping
+    phys_mem_offset + 0xb8000u64,
+    // the kernel image and stack, mapped by 4KiB pages
+    VirtAddr::from_ptr(&KERNEL_DATA),
+    VirtAddr::from_ptr(&stack_var),
+    // not mapped at all
+    VirtAddr::new(0x_5555_8888_0000),
+  ];
+  for addr in addresses {
+    let expected = unsafe { translate_virt_address(addr, phys_mem_offset) };
+    let recursive =
+      unsafe { translate_recursive(addr, recursive_index as u16) };
+    assert_eq!(recursive, expected, "translating {:?}", addr);
+  }
+
+  level4_table[recursive_index].set_unused();
+  tlb::flush_all();
+
+  serial_println!("[ok]");
+  exit_qemu(QemuExitCode::Success);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+  blog_os::test_harness::test_panic_handler(info)
+}
@@ -0,0 +1,88 @@
+//! Tests that printing to the serial port from an interrupt handler
+//! doesn't deadlock against printing from normal context, because the
+//! serial lock is never held while interrupts are enabled.
+//!
+//! A fast timer interrupts a loop that prints all the time, so many of
+//! the interrupts arrive while a line is being printed.
+
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use blog_os::{
+  interrupts::{self, PICS},
+  serial::SERIAL1,
+  serial_println,
+  test_harness::{exit_qemu, QemuExitCode},
+};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use lazy_static::lazy_static;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+/// Number of timer interrupts that have to print a line
+const ROUNDS: usize = 50;
+/// Timer frequency, high enough to hit the printing loop often
+const TIMER_HZ: u32 = 1000;
+/// The PIT is the first line of the primary PIC
+const TIMER_VECTOR: u8 = interrupts::PIC_1_OFFSET;
+
+/// Number of lines printed by the timer handler
+static HANDLED: AtomicUsize = AtomicUsize::new(0);
+/// Set if the timer interrupted code holding the serial port lock
+static LOCKED: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+  static ref TEST_IDT: InterruptDescriptorTable = {
+    let mut idt = InterruptDescriptorTable::new();
+    idt[usize::from(TIMER_VECTOR)].set_handler_fn(test_timer_handler);
+    idt
+  };
+}
+
+extern "x86-interrupt" fn test_timer_handler(_frame: InterruptStackFrame) {
+  // printing now would spin forever on the interrupted code's lock, so
+  // only record it for the loop to report
+  if SERIAL1.try_lock().is_none() {
+    LOCKED.store(true, Ordering::SeqCst);
+  } else {
+    let round = HANDLED.fetch_add(1, Ordering::SeqCst);
+    serial_println!("  timer handler line {}", round);
+  }
+  unsafe {
+    PICS.lock().notify_end_of_interrupt(TIMER_VECTOR);
+  }
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+  serial_println!("serial_interrupts::print_from_timer...");
+  TEST_IDT.load();
+  unsafe {
+    let mut pics = PICS.lock();
+    pics.initialize();
+    // only the timer has a handler
+    pics.write_masks(0xfe, 0xff);
+  }
+  interrupts::init_pit(TIMER_HZ);
+  x86_64::instructions::interrupts::enable();
+
+  let mut line = 0;
+  while HANDLED.load(Ordering::SeqCst) < ROUNDS {
+    serial_println!("  normal context line {}", line);
+    line += 1;
+    assert!(
+      !LOCKED.load(Ordering::SeqCst),
+      "serial port locked while handling an interrupt"
+    );
+  }
+
+  x86_64::instructions::interrupts::disable();
+  serial_println!("[ok]");
+  exit_qemu(QemuExitCode::Success);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+  blog_os::test_harness::test_panic_handler(info)
+}
@@ -0,0 +1,45 @@
+//! Test that a page unmapped through the [memory] module no longer
+//! translates to a physical address.
+
+#![no_std]
+#![no_main]
+
+use blog_os::{
+  memory::{self, BootInfoFrameAllocator},
+  serial_print, serial_println,
+  test_harness::{exit_qemu, QemuExitCode},
+};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::{
+  structures::paging::{Page, Translate},
+  VirtAddr,
+};
+
+entry_point!(test_kernel_entry);
+
+fn test_kernel_entry(boot_info: &'static BootInfo) -> ! {
+  serial_print!("unmap_page::unmap_page...\t");
+
+  let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+  let mut mapper = unsafe { memory::init_offset_page_table(phys_mem_offset) };
+  let mut frame_allocator =
+    unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+
+  let addr = VirtAddr::new(0x_5555_5555_0000);
+  let page = Page::containing_address(addr);
+  memory::create_example_mapping(page, &mut mapper, &mut frame_allocator);
+  assert!(mapper.translate_addr(addr).is_some());
+
+  let frame = memory::unmap_page(page, &mut mapper).expect("unmap failed");
+  assert_eq!(frame.start_address().as_u64(), 0xb8000);
+  assert!(mapper.translate_addr(addr).is_none());
+
+  serial_println!("[ok]");
+  exit_qemu(QemuExitCode::Success);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+  blog_os::test_harness::test_panic_handler(info)
+}
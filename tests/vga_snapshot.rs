@@ -0,0 +1,52 @@
+//! Test the VGA output from outside of the crate through
+//! [blog_os::vga_buffer::snapshot].
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_harness::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+
+use blog_os::test_harness::{exit_qemu, test_panic_handler, QemuExitCode};
+use blog_os::vga_buffer::{self, Color, BUFFER_HEIGHT, BUFFER_WIDTH};
+use blog_os::{cprintln, println};
+
+#[no_mangle] // don't mangle the name of this function
+pub extern "C" fn _start() -> ! {
+  test_main();
+  exit_qemu(QemuExitCode::Success);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+  test_panic_handler(info)
+}
+
+#[test_case]
+fn test_snapshot_after_clearing() {
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    // scroll everything off the screen, then print a line in red
+    for _ in 0..BUFFER_HEIGHT {
+      println!();
+    }
+    cprintln!(Color::Red, Color::Black, "hello");
+
+    let screen = vga_buffer::snapshot();
+    let red = Color::Red as u8;
+    for (row, cells) in screen.iter().enumerate() {
+      for (col, &(byte, color)) in cells.iter().enumerate() {
+        if row == BUFFER_HEIGHT - 2 && col < 5 {
+          assert_eq!(byte, b"hello"[col]);
+          assert_eq!(color, red);
+        } else {
+          assert_eq!(byte, b' ', "unexpected byte at ({}, {})", row, col);
+        }
+      }
+    }
+    assert_eq!(screen[0].len(), BUFFER_WIDTH);
+  });
+}